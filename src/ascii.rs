@@ -0,0 +1,172 @@
+use crate::computer::{Computer, IntcodeError, State};
+use std::io::{self, BufRead, Write};
+
+// Everything a program printed before it stopped. Outputs outside the ASCII
+// range are usually answers rather than text, so they're kept separately.
+#[derive(Debug, PartialEq)]
+pub struct Reply {
+    pub text: String,
+    pub values: Vec<i64>,
+    pub state: State,
+}
+
+fn as_char(value: i64) -> Option<char> {
+    if (0..=127).contains(&value) {
+        Some(value as u8 as char)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct Ascii {
+    pub computer: Computer,
+}
+
+impl Ascii {
+    pub fn new(computer: Computer) -> Self {
+        Self { computer }
+    }
+
+    pub fn send_line(&mut self, line: &str) {
+        for byte in line.bytes() {
+            self.computer.add_input(byte as i64);
+        }
+        self.computer.add_input(b'\n' as i64);
+    }
+
+    // Runs until the program wants input (or stops for any other reason).
+    pub fn read_until_prompt(&mut self) -> Result<Reply, IntcodeError> {
+        let mut text = String::new();
+        let mut values = Vec::new();
+        loop {
+            match self.computer.run()? {
+                State::Output(value) => match as_char(value) {
+                    Some(c) => text.push(c),
+                    None => values.push(value),
+                },
+                state => {
+                    return Ok(Reply {
+                        text,
+                        values,
+                        state,
+                    })
+                }
+            }
+        }
+    }
+
+    // Out-of-band values are printed on a line of their own, in the order they
+    // arrive. Returns once the program halts or the input runs dry.
+    pub fn interactive<R: BufRead, W: Write>(
+        &mut self,
+        input: R,
+        mut output: W,
+    ) -> io::Result<State> {
+        let mut lines = input.lines();
+        loop {
+            let state = self.computer.run().map_err(io::Error::other)?;
+            match state {
+                State::Output(value) => match as_char(value) {
+                    Some(c) => write!(output, "{}", c)?,
+                    None => writeln!(output, "{}", value)?,
+                },
+                State::AwaitingInput => {
+                    output.flush()?;
+                    match lines.next() {
+                        Some(line) => self.send_line(&line?),
+                        None => return Ok(State::AwaitingInput),
+                    }
+                }
+                state => {
+                    output.flush()?;
+                    return Ok(state);
+                }
+            }
+        }
+    }
+
+    pub fn interactive_stdio(&mut self) -> io::Result<State> {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        self.interactive(stdin.lock(), stdout.lock())
+    }
+}
+
+// Plays an ASCII program from the terminal until it halts or stdin ends.
+pub fn play_stdio(program: Vec<i64>) -> io::Result<()> {
+    match Ascii::new(Computer::new(program)).interactive_stdio()? {
+        State::Complete | State::AwaitingInput => Ok(()),
+        state => Err(io::Error::other(format!("stopped with {:?}", state))),
+    }
+}
+
+// Prompts with "> ", echoes each line upper-cased followed by its length as a
+// numeric result, and halts on an empty line.
+fn shouter() -> Computer {
+    let program = crate::assembler::assemble(
+        "
+    prompt: OUT #62
+            OUT #32
+            ADD #0, #0, [n]
+    read:   IN [c]
+            EQ [c], #10, [t]
+            JT [t], #done
+            ADD [c], #-32, [c]
+            OUT [c]
+            ADD [n], #1, [n]
+            JT #1, #read
+    done:   JF [n], #end
+            OUT #10
+            MUL [n], #1000, [t]
+            OUT [t]
+            JT #1, #prompt
+    end:    HLT
+    n:      .data 0
+    c:      .data 0
+    t:      .data 0
+    ",
+    )
+    .unwrap();
+    Computer::new(program)
+}
+
+#[test]
+pub fn lines_and_prompts() {
+    let mut ascii = Ascii::new(shouter());
+    let reply = ascii.read_until_prompt().unwrap();
+    assert_eq!(reply.text, "> ");
+    assert_eq!(reply.state, State::AwaitingInput);
+
+    ascii.send_line("hello");
+    assert_eq!(
+        ascii.read_until_prompt(),
+        Ok(Reply {
+            text: "HELLO\n> ".to_string(),
+            values: vec![5000],
+            state: State::AwaitingInput,
+        })
+    );
+
+    ascii.send_line("");
+    let reply = ascii.read_until_prompt().unwrap();
+    assert_eq!(reply.text, "");
+    assert_eq!(reply.state, State::Complete);
+}
+
+#[test]
+pub fn interactive_session() {
+    let mut ascii = Ascii::new(shouter());
+    let mut out = Vec::new();
+    let state = ascii.interactive("abc\nxy\n".as_bytes(), &mut out).unwrap();
+    assert_eq!(state, State::AwaitingInput);
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "> ABC\n3000\n> XY\n2000\n> "
+    );
+
+    let mut out = Vec::new();
+    let state = ascii.interactive("\n".as_bytes(), &mut out).unwrap();
+    assert_eq!(state, State::Complete);
+    assert!(out.is_empty());
+}
@@ -0,0 +1,481 @@
+use crate::computer::{ParamKind, ParamMode, OPCODES};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+// Long-form names, so source can be written in terms of the `Operation` enum too.
+const ALIASES: &[(&str, &str)] = &[
+    ("MULTIPLY", "MUL"),
+    ("INPUT", "IN"),
+    ("OUTPUT", "OUT"),
+    ("JUMPTRUE", "JT"),
+    ("JUMPFALSE", "JF"),
+    ("LESSTHAN", "LT"),
+    ("EQUALTO", "EQ"),
+    ("RELATIVEBASE", "ARB"),
+    ("EXIT", "HLT"),
+];
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(i64),
+    Label(String),
+    Negate(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, labels: &HashMap<String, i64>) -> Result<i64, String> {
+        match self {
+            Expr::Number(n) => Ok(*n),
+            Expr::Label(name) => labels
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("undefined label '{}'", name)),
+            Expr::Negate(e) => {
+                let a = e.eval(labels)?;
+                a.checked_neg().ok_or_else(|| format!("overflow in -{}", a))
+            }
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(labels)?, b.eval(labels)?);
+                match op {
+                    '+' => a.checked_add(b),
+                    '-' => a.checked_sub(b),
+                    _ => a.checked_mul(b),
+                }
+                .ok_or_else(|| format!("overflow in {} {} {}", a, op, b))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Symbol(char),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let digits: String = chars[start..i].iter().collect();
+            let n = digits
+                .parse()
+                .map_err(|_| format!("number '{}' out of range", digits))?;
+            tokens.push(Token::Number(n));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if "+-*()".contains(c) {
+            tokens.push(Token::Symbol(c));
+            i += 1;
+        } else {
+            return Err(format!("unexpected character '{}'", c));
+        }
+    }
+    Ok(tokens)
+}
+
+struct ExprParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn parse(text: &str) -> Result<Expr, String> {
+        let mut parser = Self {
+            tokens: tokenize(text)?,
+            pos: 0,
+        };
+        let expr = parser.sum()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {:?} in '{}'", token, text.trim())),
+        }
+    }
+
+    fn peek_symbol(&self, symbols: &str) -> Option<char> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Symbol(c)) if symbols.contains(*c) => Some(*c),
+            _ => None,
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        while let Some(op) = self.peek_symbol("+-") {
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while let Some(op) = self.peek_symbol("*") {
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Symbol('-')) => Ok(Expr::Negate(Box::new(self.unary()?))),
+            Some(Token::Symbol('+')) => self.unary(),
+            Some(Token::Symbol('(')) => {
+                let expr = self.sum()?;
+                if self.peek_symbol(")").is_none() {
+                    return Err("missing ')'".to_string());
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Ident(name)) => Ok(Expr::Label(name)),
+            _ => Err("expected a value".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Operand {
+    mode: ParamMode,
+    expr: Expr,
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+    let text = text.trim();
+    let (mode, expr) = if let Some(rest) = text.strip_prefix('#') {
+        (ParamMode::Immediate, ExprParser::parse(rest)?)
+    } else if text.starts_with('[') && text.ends_with(']') {
+        (
+            ParamMode::Position,
+            ExprParser::parse(&text[1..text.len() - 1])?,
+        )
+    } else if let Some(rest) = text.strip_prefix("rb") {
+        let rest = rest.trim_start();
+        if !(rest.is_empty() || rest.starts_with('+') || rest.starts_with('-')) {
+            return Err(format!("bad relative operand '{}'", text));
+        }
+        let expr = if rest.is_empty() {
+            Expr::Number(0)
+        } else {
+            ExprParser::parse(rest)?
+        };
+        (ParamMode::Relative, expr)
+    } else {
+        return Err(format!(
+            "operand '{}' needs a mode: #imm, [pos] or rb+N",
+            text
+        ));
+    };
+    Ok(Operand { mode, expr })
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Instruction { code: i64, operands: Vec<Operand> },
+    Data(Vec<Expr>),
+}
+
+impl Statement {
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instruction { operands, .. } => operands.len() + 1,
+            Statement::Data(values) => values.len(),
+        }
+    }
+}
+
+fn split_operands(text: &str) -> Vec<&str> {
+    if text.trim().is_empty() {
+        Vec::new()
+    } else {
+        text.split(',').map(|s| s.trim()).collect()
+    }
+}
+
+fn instruction(mnemonic: &str, operands: Vec<Operand>) -> Result<Statement, String> {
+    let name = ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == mnemonic)
+        .map_or(mnemonic, |(_, name)| name);
+    let info = OPCODES
+        .iter()
+        .find(|op| op.mnemonic == name)
+        .ok_or_else(|| format!("unknown mnemonic '{}'", mnemonic))?;
+    if operands.len() != info.params.len() {
+        return Err(format!(
+            "{} takes {} operands, got {}",
+            info.mnemonic,
+            info.params.len(),
+            operands.len()
+        ));
+    }
+    for (operand, kind) in operands.iter().zip(info.params) {
+        if *kind == ParamKind::Write && operand.mode == ParamMode::Immediate {
+            return Err(format!("{} can't write to an immediate", info.mnemonic));
+        }
+    }
+    Ok(Statement::Instruction {
+        code: info.code,
+        operands,
+    })
+}
+
+// CALL stores the return address at rb+0 for the callee, and RET jumps back through it,
+// matching the convention compiled Intcode programs use (e.g. `21101,0,367,0,1106,0,471`).
+fn parse_statement(text: &str, address: usize) -> Result<Vec<Statement>, String> {
+    let (head, rest) = match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], &text[i..]),
+        None => (text, ""),
+    };
+    let mnemonic = head.to_uppercase();
+    match mnemonic.as_str() {
+        ".DATA" | "DATA" => {
+            let values = split_operands(rest)
+                .into_iter()
+                .map(ExprParser::parse)
+                .collect::<Result<Vec<_>, _>>()?;
+            if values.is_empty() {
+                return Err(".data needs at least one value".to_string());
+            }
+            Ok(vec![Statement::Data(values)])
+        }
+        "CALL" => {
+            let target = ExprParser::parse(rest)?;
+            let ret = Operand {
+                mode: ParamMode::Immediate,
+                expr: Expr::Number(address as i64 + 7),
+            };
+            let imm = |n| Operand {
+                mode: ParamMode::Immediate,
+                expr: Expr::Number(n),
+            };
+            let rb = Operand {
+                mode: ParamMode::Relative,
+                expr: Expr::Number(0),
+            };
+            let target = Operand {
+                mode: ParamMode::Immediate,
+                expr: target,
+            };
+            Ok(vec![
+                instruction("ADD", vec![ret, imm(0), rb])?,
+                instruction("JT", vec![imm(1), target])?,
+            ])
+        }
+        "RET" => {
+            if !rest.trim().is_empty() {
+                return Err("RET takes no operands".to_string());
+            }
+            let imm = Operand {
+                mode: ParamMode::Immediate,
+                expr: Expr::Number(1),
+            };
+            let rb = Operand {
+                mode: ParamMode::Relative,
+                expr: Expr::Number(0),
+            };
+            Ok(vec![instruction("JT", vec![imm, rb])?])
+        }
+        _ => {
+            let operands = split_operands(rest)
+                .into_iter()
+                .map(parse_operand)
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(vec![instruction(&mnemonic, operands)?])
+        }
+    }
+}
+
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_alphanumeric() || c == '_') && name != "rb"
+        }
+        _ => false,
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Vec<i64>, AssembleError> {
+    let mut labels: HashMap<String, i64> = HashMap::new();
+    let mut statements: Vec<(usize, Statement)> = Vec::new();
+    let mut address = 0;
+
+    for (n, line) in source.lines().enumerate() {
+        let error = |message| AssembleError {
+            line: n + 1,
+            message,
+        };
+        let mut text = line.split(';').next().unwrap().trim();
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if !is_label(label) {
+                break;
+            }
+            if labels.insert(label.to_string(), address as i64).is_some() {
+                return Err(error(format!("label '{}' defined twice", label)));
+            }
+            text = text[colon + 1..].trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+        for statement in parse_statement(text, address).map_err(error)? {
+            address += statement.size();
+            statements.push((n + 1, statement));
+        }
+    }
+
+    let mut program = Vec::with_capacity(address);
+    for (line, statement) in statements {
+        let error = |message| AssembleError { line, message };
+        match statement {
+            Statement::Instruction { code, operands } => {
+                let mut word = code;
+                let mut scale = 100;
+                for operand in &operands {
+                    word += operand.mode.digit() * scale;
+                    scale *= 10;
+                }
+                program.push(word);
+                for operand in operands {
+                    program.push(operand.expr.eval(&labels).map_err(error)?);
+                }
+            }
+            Statement::Data(values) => {
+                for value in values {
+                    program.push(value.eval(&labels).map_err(error)?);
+                }
+            }
+        }
+    }
+
+    Ok(program)
+}
+
+#[test]
+pub fn day5_equal_to_eight() {
+    let program = assemble(
+        "
+        IN [x]
+        EQ [x], [eight], [x]    ; x = (x == 8)
+        OUT [x]
+        HLT
+    x:      .data -1
+    eight:  .data 8
+    ",
+    )
+    .unwrap();
+    assert_eq!(program, [3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]);
+}
+
+#[test]
+pub fn day9_relative_modes() {
+    let program = assemble("ARB #1\nIN rb+2\nOUT rb+2\nHLT").unwrap();
+    assert_eq!(program, [109, 1, 203, 2, 204, 2, 99]);
+    let program = assemble("ARB #-1\nOUT rb - 1 + 2\nHLT").unwrap();
+    assert_eq!(program, [109, -1, 204, 1, 99]);
+}
+
+#[test]
+pub fn labels_and_expressions() {
+    let program = assemble(
+        "
+    start:  JumpTrue #1, #end
+    table:  .data 1, 2, table + 2 * (3 - 1)
+    end:    Exit
+    ",
+    )
+    .unwrap();
+    assert_eq!(program, [1105, 1, 6, 1, 2, 7, 99]);
+}
+
+#[test]
+pub fn call_and_ret() {
+    let program = assemble(
+        "
+        ARB #100
+        CALL double
+        OUT rb+1
+        HLT
+    double:
+        MUL rb+1, #2, rb+1
+        RET
+    ",
+    )
+    .unwrap();
+    assert_eq!(
+        &program[..12],
+        &[109, 100, 21101, 9, 0, 0, 1105, 1, 12, 204, 1, 99]
+    );
+    assert_eq!(&program[12..], &[21202, 1, 2, 1, 2105, 1, 0]);
+}
+
+#[test]
+pub fn errors_carry_line_numbers() {
+    let err = assemble("HLT\nADD #1, #2, #3").unwrap_err();
+    assert_eq!(err.line, 2);
+    let err = assemble("JT #1, #nowhere").unwrap_err();
+    assert_eq!(err.message, "undefined label 'nowhere'");
+    let err = assemble("FOO [1]").unwrap_err();
+    assert_eq!(err.message, "unknown mnemonic 'FOO'");
+}
+
+#[test]
+pub fn overflow_is_an_error() {
+    let err = assemble("HLT\n.data 9223372036854775807+1").unwrap_err();
+    assert_eq!(err.line, 2);
+    assert_eq!(err.message, "overflow in 9223372036854775807 + 1");
+    let err = assemble(".data 2*4611686018427387904").unwrap_err();
+    assert_eq!(err.line, 1);
+    assert!(assemble("JT #1, #0-9223372036854775807-2").is_err());
+}
+
+#[test]
+pub fn round_trip_with_disassembler() {
+    use crate::disassembler::disassemble;
+    let original = [
+        3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0,
+        1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20,
+        1105, 1, 46, 98, 99, 109, -3, 2105, 1, 0,
+    ];
+    let source: Vec<String> = disassemble(&original, None)
+        .lines
+        .iter()
+        .map(|line| line.decoded.to_string())
+        .collect();
+    assert_eq!(assemble(&source.join("\n")).unwrap(), original.to_vec());
+}
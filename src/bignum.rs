@@ -0,0 +1,211 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Add, Mul};
+
+// Sign and magnitude, with the magnitude in little-endian base 2^32 limbs.
+// There are never any high zero limbs, and zero is never negative.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    limbs: Vec<u32>,
+}
+
+fn trim(limbs: &mut Vec<u32>) {
+    while limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+}
+
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut sum = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u64;
+    for i in 0..a.len().max(b.len()) {
+        let total = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        sum.push(total as u32);
+        carry = total >> 32;
+    }
+    sum.push(carry as u32);
+    trim(&mut sum);
+    sum
+}
+
+// Requires |a| >= |b|.
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut difference = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &limb) in a.iter().enumerate() {
+        let mut total = limb as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if total < 0 {
+            total += 1 << 32;
+            borrow = 1;
+        }
+        difference.push(total as u32);
+    }
+    trim(&mut difference);
+    difference
+}
+
+fn mul_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut product = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let total = x as u64 * y as u64 + product[i + j] as u64 + carry;
+            product[i + j] = total as u32;
+            carry = total >> 32;
+        }
+        product[i + b.len()] = carry as u32;
+    }
+    trim(&mut product);
+    product
+}
+
+impl BigInt {
+    fn new(negative: bool, limbs: Vec<u32>) -> Self {
+        Self {
+            negative: negative && !limbs.is_empty(),
+            limbs,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.limbs.len() > 2 {
+            return None;
+        }
+        let magnitude = self
+            .limbs
+            .iter()
+            .rev()
+            .fold(0u64, |n, &limb| (n << 32) | limb as u64);
+        if self.negative {
+            if magnitude <= 1 << 63 {
+                Some((magnitude as i64).wrapping_neg())
+            } else {
+                None
+            }
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+
+    // The nearest i64, for places that can only hold 64 bits.
+    pub fn saturate(&self) -> i64 {
+        match self.to_i64() {
+            Some(n) => n,
+            None if self.negative => i64::MIN,
+            None => i64::MAX,
+        }
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> Self {
+        let magnitude = n.unsigned_abs();
+        let mut limbs = vec![magnitude as u32, (magnitude >> 32) as u32];
+        trim(&mut limbs);
+        Self::new(n < 0, limbs)
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_magnitude(&self.limbs, &other.limbs));
+        }
+        match cmp_magnitude(&self.limbs, &other.limbs) {
+            Ordering::Less => BigInt::new(other.negative, sub_magnitude(&other.limbs, &self.limbs)),
+            _ => BigInt::new(self.negative, sub_magnitude(&self.limbs, &other.limbs)),
+        }
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::new(
+            self.negative != other.negative,
+            mul_magnitude(&self.limbs, &other.limbs),
+        )
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitude(&self.limbs, &other.limbs),
+            (true, true) => cmp_magnitude(&other.limbs, &self.limbs),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // Peel off nine decimal digits at a time, least significant first.
+        let mut chunks = Vec::new();
+        let mut limbs = self.limbs.clone();
+        while !limbs.is_empty() {
+            let mut remainder = 0u64;
+            for limb in limbs.iter_mut().rev() {
+                let n = (remainder << 32) | *limb as u64;
+                *limb = (n / 1_000_000_000) as u32;
+                remainder = n % 1_000_000_000;
+            }
+            trim(&mut limbs);
+            chunks.push(remainder);
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        write!(f, "{}", chunks.next().unwrap())?;
+        for chunk in chunks {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+pub fn arithmetic() {
+    let max = BigInt::from(i64::MAX);
+    let min = BigInt::from(i64::MIN);
+    let one = BigInt::from(1);
+    assert_eq!((&max + &one).to_string(), "9223372036854775808");
+    assert_eq!((&max + &one).to_i64(), None);
+    assert_eq!(min.to_i64(), Some(i64::MIN));
+    assert_eq!((&min + &max).to_i64(), Some(-1));
+    assert_eq!(
+        (&min * &min).to_string(),
+        "85070591730234615865843651857942052864"
+    );
+    assert_eq!((&min * &one).to_string(), "-9223372036854775808");
+    assert_eq!((&BigInt::from(-5) + &BigInt::from(5)), BigInt::from(0));
+    assert_eq!(BigInt::from(1_000_000_000).to_string(), "1000000000");
+    assert!(min < BigInt::from(-1) && BigInt::from(-1) < max && &max * &max > max);
+}
@@ -0,0 +1,46 @@
+use advent_of_code_2019::{debug_stdio, play_stdio};
+use std::env;
+use std::fs;
+use std::process;
+
+const USAGE: &str = "usage: intcode debug|ascii <program>";
+
+// A program file as the puzzles give them: comma-separated integers.
+fn load(path: &str) -> Result<Vec<i64>, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    text.trim()
+        .split(',')
+        .map(|word| {
+            word.trim()
+                .parse()
+                .map_err(|_| format!("{}: '{}' is not an integer", path, word.trim()))
+        })
+        .collect()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (command, path) = match args.as_slice() {
+        [command, path] => (command.as_str(), path.as_str()),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    let program = load(path).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+    let result = match command {
+        "debug" => debug_stdio(program),
+        "ascii" => play_stdio(program),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,212 @@
+use crate::computer::{TraceEvent, Tracer};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+// One activation of a subroutine. Compiled Intcode passes the return address in
+// the caller's rb+0 and arguments in rb+1 upwards, so `frame` is the relative
+// base at the call and `results` are the argument cells the callee overwrote,
+// as (offset from `frame`, final value).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub entry: usize,
+    pub site: usize,
+    pub return_address: usize,
+    pub frame: i64,
+    pub arguments: Vec<i64>,
+    pub results: Vec<(usize, i64)>,
+    pub returned: bool,
+    pub calls: Vec<Call>,
+}
+
+impl Call {
+    fn render(&self, depth: usize, out: &mut String) {
+        let arguments: Vec<String> = self.arguments.iter().map(|a| a.to_string()).collect();
+        write!(
+            out,
+            "{:indent$}{}({}) from {}",
+            "",
+            self.entry,
+            arguments.join(", "),
+            self.site,
+            indent = depth * 2
+        )
+        .unwrap();
+        if self.returned {
+            let results: Vec<String> = self
+                .results
+                .iter()
+                .map(|(offset, value)| format!("rb+{}={}", offset, value))
+                .collect();
+            write!(out, " -> {}", results.join(", ")).unwrap();
+        } else {
+            write!(out, " [active]").unwrap();
+        }
+        out.push('\n');
+        for call in &self.calls {
+            call.render(depth + 1, out);
+        }
+    }
+}
+
+// Recognises calls and returns from a trace. A taken jump is a call when the
+// word at rb+0 was just set to the address after it, and a return when it lands
+// on an open call's return address with the relative base back at its frame.
+// Arguments are the run of cells from rb+1 that the caller wrote (or got back
+// from a callee) since its last call or return.
+#[derive(Debug, Default)]
+pub struct CallTracker {
+    roots: Vec<Call>,
+    stack: Vec<Call>,
+    cells: HashMap<usize, i64>,
+    written: BTreeSet<usize>,
+}
+
+impl CallTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Open calls, innermost first.
+    pub fn backtrace(&self) -> impl Iterator<Item = &Call> {
+        self.stack.iter().rev()
+    }
+
+    // The whole call tree so far, with still-open calls marked as not returned.
+    pub fn calls(&self) -> Vec<Call> {
+        let mut roots = self.roots.clone();
+        let mut open = self.stack.clone();
+        while let Some(call) = open.pop() {
+            match open.last_mut() {
+                Some(parent) => parent.calls.push(call),
+                None => roots.push(call),
+            }
+        }
+        roots
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        for call in self.calls() {
+            call.render(0, &mut out);
+        }
+        out
+    }
+
+    fn write(&mut self, address: usize, value: i64) {
+        self.cells.insert(address, value);
+        self.written.insert(address);
+        if let Some(call) = self.stack.last_mut() {
+            let offset = address as i64 - call.frame;
+            if offset >= 1 && offset as usize <= call.arguments.len().max(1) {
+                let offset = offset as usize;
+                match call.results.binary_search_by_key(&offset, |&(o, _)| o) {
+                    Ok(i) => call.results[i].1 = value,
+                    Err(i) => call.results.insert(i, (offset, value)),
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, site: usize, entry: usize, relative_base: i64) {
+        let return_address = site + 3;
+        let arguments = (1..)
+            .map(|offset| (relative_base + offset) as usize)
+            .take_while(|address| self.written.contains(address))
+            .map(|address| self.cells[&address])
+            .collect();
+        self.written.clear();
+        self.stack.push(Call {
+            entry,
+            site,
+            return_address,
+            frame: relative_base,
+            arguments,
+            results: Vec::new(),
+            returned: false,
+            calls: Vec::new(),
+        });
+    }
+
+    // Closes the call being returned from, along with any calls above it that
+    // were abandoned without returning.
+    fn ret(&mut self, depth: usize) {
+        while self.stack.len() > depth {
+            let mut call = self.stack.pop().unwrap();
+            call.returned = self.stack.len() == depth;
+            self.written.clear();
+            if call.returned {
+                for &(offset, _) in &call.results {
+                    self.written.insert((call.frame + offset as i64) as usize);
+                }
+            }
+            match self.stack.last_mut() {
+                Some(parent) => parent.calls.push(call),
+                None => self.roots.push(call),
+            }
+        }
+    }
+}
+
+impl Tracer for CallTracker {
+    fn record(&mut self, event: &TraceEvent) {
+        for &(address, value) in &event.writes {
+            self.write(address, value);
+        }
+        let taken = match (event.instruction % 100, event.operands.as_slice()) {
+            (5, &[condition, target]) if condition != 0 => target,
+            (6, &[0, target]) => target,
+            _ => return,
+        };
+        if taken < 0 {
+            return;
+        }
+        let (target, rb) = (taken as usize, event.relative_base);
+        let returning = self
+            .stack
+            .iter()
+            .rposition(|call| call.return_address == target && call.frame == rb);
+        if let Some(depth) = returning {
+            self.ret(depth);
+            return;
+        }
+        let slot = rb as usize;
+        let linked = rb >= 0
+            && self.written.contains(&slot)
+            && self.cells.get(&slot) == Some(&(event.ix as i64 + 3));
+        if linked && target != event.ix + 3 {
+            self.call(event.ix, target, rb);
+        }
+    }
+}
+
+#[test]
+pub fn call_tree() {
+    use crate::computer::{Computer, State};
+    use std::sync::{Arc, Mutex};
+
+    let tracker = Arc::new(Mutex::new(CallTracker::new()));
+    let mut computer = Computer::new(crate::samples::nested_calls());
+    computer.set_tracer(tracker.clone());
+    assert_eq!(computer.run(), Ok(State::Output(12)));
+    let tracker = tracker.lock().unwrap();
+    assert_eq!(tracker.backtrace().count(), 0);
+    let calls = tracker.calls();
+    assert_eq!(calls.len(), 1);
+    let twice = &calls[0];
+    assert_eq!((twice.frame, twice.site), (100, 10));
+    assert_eq!(twice.arguments, [3]);
+    assert_eq!(twice.results, [(1, 12)]);
+    let doubles: Vec<_> = twice
+        .calls
+        .iter()
+        .map(|call| (call.arguments.clone(), call.results.clone()))
+        .collect();
+    assert_eq!(doubles, [(vec![3], vec![(1, 6)]), (vec![6], vec![(1, 12)])]);
+    assert_eq!(
+        tracker.report(),
+        format!(
+            "{0}(3) from 10 -> rb+1=12\n  {1}(3) from {2} -> rb+1=6\n  {1}(6) from {3} -> rb+1=12\n",
+            twice.entry, twice.calls[0].entry, twice.calls[0].site, twice.calls[1].site
+        )
+    );
+}
@@ -0,0 +1,383 @@
+use crate::computer::{OpcodeInfo, ParamMode};
+use crate::disassembler::{decode_at, Decoded, Operand};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub address: usize,
+    pub info: &'static OpcodeInfo,
+    pub operands: Vec<Operand>,
+}
+
+impl Instruction {
+    pub fn next(&self) -> usize {
+        self.address + 1 + self.operands.len()
+    }
+
+    pub fn decoded(&self) -> Decoded {
+        Decoded::Instruction {
+            mnemonic: self.info.mnemonic,
+            operands: self.operands.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    FallThrough,
+    Taken,
+    // A computed jump, assumed able to reach any return address the program pushes.
+    Indirect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    pub instructions: Vec<Instruction>,
+}
+
+// Blocks are keyed by their first address. `invalid` holds addresses control
+// can reach that don't decode as an instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    pub blocks: BTreeMap<usize, Block>,
+    pub edges: Vec<Edge>,
+    pub invalid: BTreeSet<usize>,
+}
+
+enum Flow {
+    Next,
+    Stop,
+    Goto(usize),
+    Branch(usize),
+    Indirect { conditional: bool },
+}
+
+fn flow(instruction: &Instruction) -> Flow {
+    let code = instruction.info.code;
+    if code == 99 {
+        return Flow::Stop;
+    }
+    if code != 5 && code != 6 {
+        return Flow::Next;
+    }
+    let (condition, target) = (instruction.operands[0], instruction.operands[1]);
+    let decided = match condition.mode {
+        ParamMode::Immediate => Some((condition.value != 0) == (code == 5)),
+        _ => None,
+    };
+    match (decided, target.mode) {
+        (Some(false), _) => Flow::Next,
+        // A jump to a negative address faults.
+        (Some(true), ParamMode::Immediate) if target.value < 0 => Flow::Stop,
+        (None, ParamMode::Immediate) if target.value < 0 => Flow::Next,
+        (Some(true), ParamMode::Immediate) => Flow::Goto(target.value as usize),
+        (None, ParamMode::Immediate) => Flow::Branch(target.value as usize),
+        (decided, _) => Flow::Indirect {
+            conditional: decided.is_none(),
+        },
+    }
+}
+
+// The return address a CALL pushes: `ADD #x, #0, rb+k` or `MUL #x, #1, rb+k`
+// right before a jump that's always taken to a fixed address. Other copies of
+// literals are usually plain data and shouldn't become jump targets.
+fn pushed_return(program: &[i64], instruction: &Instruction) -> Option<i64> {
+    let identity = match instruction.info.code {
+        1 => 0,
+        2 => 1,
+        _ => return None,
+    };
+    if instruction.operands[2].mode != ParamMode::Relative {
+        return None;
+    }
+    let literal = match instruction.operands[..2] {
+        [a, b] if a.mode == ParamMode::Immediate && b.mode == ParamMode::Immediate => {
+            if b.value == identity {
+                a.value
+            } else if a.value == identity {
+                b.value
+            } else {
+                return None;
+            }
+        }
+        _ => return None,
+    };
+    let (info, operands) = decode_at(program, instruction.next())?;
+    let jump = Instruction {
+        address: instruction.next(),
+        info,
+        operands,
+    };
+    match flow(&jump) {
+        Flow::Goto(_) => Some(literal),
+        _ => None,
+    }
+}
+
+// Recovers the code reachable from address 0. Jumps with immediate targets are
+// followed exactly; computed ones are assumed to reach any return address a
+// CALL pushes, which over-approximates the return sites of each subroutine.
+pub fn build(program: &[i64]) -> Cfg {
+    let mut instructions: BTreeMap<usize, Instruction> = BTreeMap::new();
+    let mut invalid = BTreeSet::new();
+    let mut leaders = BTreeSet::new();
+    let mut literals = BTreeSet::new();
+    let mut indirect = false;
+    let mut work = vec![0];
+    leaders.insert(0);
+
+    loop {
+        while let Some(address) = work.pop() {
+            if instructions.contains_key(&address) || invalid.contains(&address) {
+                continue;
+            }
+            let instruction = match decode_at(program, address) {
+                Some((info, operands)) => Instruction {
+                    address,
+                    info,
+                    operands,
+                },
+                None => {
+                    invalid.insert(address);
+                    continue;
+                }
+            };
+            if let Some(literal) = pushed_return(program, &instruction) {
+                if literal >= 0 && (literal as usize) < program.len() {
+                    literals.insert(literal as usize);
+                }
+            }
+            let next = instruction.next();
+            match flow(&instruction) {
+                Flow::Next => work.push(next),
+                Flow::Stop => {}
+                Flow::Goto(target) => {
+                    leaders.insert(target);
+                    work.push(target);
+                }
+                Flow::Branch(target) => {
+                    leaders.insert(target);
+                    leaders.insert(next);
+                    work.push(target);
+                    work.push(next);
+                }
+                Flow::Indirect { conditional } => {
+                    indirect = true;
+                    if conditional {
+                        leaders.insert(next);
+                        work.push(next);
+                    }
+                }
+            }
+            instructions.insert(address, instruction);
+        }
+        if !indirect {
+            break;
+        }
+        for &literal in &literals {
+            if leaders.insert(literal) {
+                work.push(literal);
+            }
+        }
+        if work.is_empty() {
+            break;
+        }
+    }
+
+    let mut blocks = BTreeMap::new();
+    let mut edges = Vec::new();
+    for &start in &leaders {
+        if !instructions.contains_key(&start) {
+            continue;
+        }
+        let mut block = Vec::new();
+        let mut address = start;
+        let last = loop {
+            let instruction = instructions[&address].clone();
+            let next = instruction.next();
+            let ends = !matches!(flow(&instruction), Flow::Next);
+            block.push(instruction);
+            if ends || leaders.contains(&next) || !instructions.contains_key(&next) {
+                break block.last().unwrap();
+            }
+            address = next;
+        };
+        let mut edge = |to, kind| {
+            edges.push(Edge {
+                from: start,
+                to,
+                kind,
+            })
+        };
+        match flow(last) {
+            Flow::Next => edge(last.next(), EdgeKind::FallThrough),
+            Flow::Stop => {}
+            Flow::Goto(target) => edge(target, EdgeKind::Taken),
+            Flow::Branch(target) => {
+                edge(target, EdgeKind::Taken);
+                edge(last.next(), EdgeKind::FallThrough);
+            }
+            Flow::Indirect { conditional } => {
+                for &literal in &literals {
+                    edge(literal, EdgeKind::Indirect);
+                }
+                if conditional {
+                    edge(last.next(), EdgeKind::FallThrough);
+                }
+            }
+        }
+        blocks.insert(
+            start,
+            Block {
+                start,
+                instructions: block,
+            },
+        );
+    }
+    edges.sort();
+    edges.dedup();
+    Cfg {
+        blocks,
+        edges,
+        invalid,
+    }
+}
+
+impl Cfg {
+    pub fn successors(&self, block: usize) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |edge| edge.from == block)
+    }
+
+    // Graphviz source: one box per block listing its instructions, with taken
+    // branches labelled and computed jumps dotted.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph cfg {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for instruction in &block.instructions {
+                write!(
+                    label,
+                    "{}: {}\\l",
+                    instruction.address,
+                    instruction.decoded()
+                )
+                .unwrap();
+            }
+            writeln!(dot, "    n{} [label=\"{}\"];", block.start, label).unwrap();
+        }
+        for address in &self.invalid {
+            writeln!(
+                dot,
+                "    n{} [label=\"invalid {}\", color=red];",
+                address, address
+            )
+            .unwrap();
+        }
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::FallThrough => "",
+                EdgeKind::Taken => " [label=\"taken\"]",
+                EdgeKind::Indirect => " [style=dotted]",
+            };
+            writeln!(dot, "    n{} -> n{}{};", edge.from, edge.to, style).unwrap();
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+#[test]
+pub fn loop_blocks() {
+    let program = crate::samples::COUNTER;
+    let cfg = build(program);
+    assert_eq!(cfg.blocks.keys().cloned().collect::<Vec<_>>(), [0, 13]);
+    assert_eq!(cfg.blocks[&0].instructions.len(), 4);
+    assert_eq!(
+        cfg.edges,
+        [
+            Edge {
+                from: 0,
+                to: 0,
+                kind: EdgeKind::Taken
+            },
+            Edge {
+                from: 0,
+                to: 13,
+                kind: EdgeKind::FallThrough
+            },
+        ]
+    );
+    assert_eq!(
+        cfg.to_dot(),
+        "digraph cfg {\n    \
+         node [shape=box, fontname=\"monospace\"];\n    \
+         n0 [label=\"0: ADD [14], #1, [14]\\l4: OUT [14]\\l6: LT [14], #3, [15]\\l10: JT [15], #0\\l\"];\n    \
+         n13 [label=\"13: HLT\\l\"];\n    \
+         n0 -> n0 [label=\"taken\"];\n    \
+         n0 -> n13;\n\
+         }\n"
+    );
+}
+
+#[test]
+pub fn calls_through_the_stack() {
+    let program = crate::assembler::assemble(
+        "
+            ARB #100
+            CALL double
+            OUT rb+1
+            CALL double
+            HLT
+    double: MUL rb+1, #2, rb+1
+            RET
+    ",
+    )
+    .unwrap();
+    let cfg = build(&program);
+    // Entry, both return sites, and the subroutine.
+    let (first_return, second_return, double) = (9, 18, 19);
+    assert_eq!(
+        cfg.blocks.keys().cloned().collect::<Vec<_>>(),
+        [0, first_return, second_return, double]
+    );
+    let returns: Vec<usize> = cfg
+        .successors(double)
+        .filter(|edge| edge.kind == EdgeKind::Indirect)
+        .map(|edge| edge.to)
+        .collect();
+    assert_eq!(returns, [first_return, second_return]);
+    assert!(cfg.invalid.is_empty());
+    assert!(cfg.to_dot().contains("n19 -> n9 [style=dotted];"));
+}
+
+#[test]
+pub fn undecodable_code() {
+    let cfg = build(&[1105, 1, 3, 42]);
+    assert_eq!(cfg.invalid.iter().cloned().collect::<Vec<_>>(), [3]);
+    assert!(cfg
+        .to_dot()
+        .contains("n3 [label=\"invalid 3\", color=red];"));
+}
+
+#[test]
+pub fn only_return_sites_are_indirect_targets() {
+    for input in &[
+        include_str!("../input/2019/day9.txt"),
+        include_str!("../input/2019/day13.txt"),
+    ] {
+        let cfg = build(&crate::day9::input_generator(input));
+        assert!(cfg.invalid.is_empty(), "{:?}", cfg.invalid);
+        assert!(cfg.edges.iter().any(|edge| edge.kind == EdgeKind::Indirect));
+    }
+}
@@ -1,18 +1,255 @@
+use crate::bignum::BigInt;
+use crate::coverage::Coverage;
+use crate::dialect::Dialect;
+use crate::history::{History, Undo};
+use crate::memory::{Memory, PagedMemory};
+use crate::profile::Profile;
+use crate::snapshot::Snapshot;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
 #[derive(Debug)]
 pub struct Computer {
-    pub memory: Vec<i64>,
+    pub memory: Box<dyn Memory + Send>,
+    memory_limit: Option<usize>,
     ix: usize,
-    inputs: Vec<i64>,
+    inputs: VecDeque<i64>,
     relative_base: i64,
+    op_ix: usize,
+    watchpoints: Vec<Watchpoint>,
+    next_watch_id: usize,
+    hits: VecDeque<Hit>,
+    tracer: Option<Box<dyn Tracer + Send>>,
+    traced_writes: Vec<(usize, i64)>,
+    step_limit: Option<u64>,
+    cycles: u64,
+    opcode_counts: Box<[u64; 100]>,
+    arithmetic: Arithmetic,
+    // Cells whose true value doesn't fit in an i64 (big mode only). The cell
+    // itself holds the saturated value.
+    wide: BTreeMap<usize, BigInt>,
+    // Where each read parameter of the current instruction came from, if memory.
+    param_addresses: Vec<Option<usize>>,
+    engine: Engine,
+    decoded: Vec<Option<Decoded>>,
+    profile: Option<Profile>,
+    history: Option<History>,
+    dialect: Dialect,
+    coverage: Option<Coverage>,
+}
+
+// How results that don't fit in 64 bits are handled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arithmetic {
+    // Plain i64 arithmetic: panics on overflow in debug builds, wraps in release.
+    Native,
+    // Overflow stops the machine with `IntcodeError::Overflow`.
+    Checked,
+    // Oversized results are kept as `BigInt`s and output as `State::BigOutput`.
+    Big,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn covers(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub id: usize,
+    pub ix: usize,
+    pub address: usize,
+    pub access: Access,
+    pub value: i64,
+}
+
+type WatchCallback = Box<dyn FnMut(&Hit) + Send>;
+
+struct Watchpoint {
+    id: usize,
+    range: Range<usize>,
+    access: Access,
+    callback: Option<WatchCallback>,
+}
+
+impl fmt::Debug for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watchpoint")
+            .field("id", &self.id)
+            .field("range", &self.range)
+            .field("access", &self.access)
+            .field("callback", &self.callback.is_some())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntcodeError {
+    UnknownOpcode {
+        ix: usize,
+        instruction: i64,
+    },
+    InvalidParamMode {
+        ix: usize,
+        instruction: i64,
+        mode: i64,
+    },
+    NegativeAddress {
+        ix: usize,
+        instruction: i64,
+        address: i64,
+    },
+    JumpOutOfRange {
+        ix: usize,
+        instruction: i64,
+        target: i64,
+    },
+    MemoryLimit {
+        ix: usize,
+        instruction: i64,
+        address: usize,
+    },
+    Overflow {
+        ix: usize,
+        instruction: i64,
+    },
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntcodeError::UnknownOpcode { ix, instruction } => {
+                write!(f, "unknown opcode in {} at {}", instruction, ix)
+            }
+            IntcodeError::InvalidParamMode {
+                ix,
+                instruction,
+                mode,
+            } => write!(
+                f,
+                "invalid parameter mode {} in {} at {}",
+                mode, instruction, ix
+            ),
+            IntcodeError::NegativeAddress {
+                ix,
+                instruction,
+                address,
+            } => write!(
+                f,
+                "negative address {} in {} at {}",
+                address, instruction, ix
+            ),
+            IntcodeError::JumpOutOfRange {
+                ix,
+                instruction,
+                target,
+            } => write!(
+                f,
+                "jump to {} out of range in {} at {}",
+                target, instruction, ix
+            ),
+            IntcodeError::MemoryLimit {
+                ix,
+                instruction,
+                address,
+            } => write!(
+                f,
+                "write to {} exceeds memory limit in {} at {}",
+                address, instruction, ix
+            ),
+            IntcodeError::Overflow { ix, instruction } => {
+                write!(f, "arithmetic overflow in {} at {}", instruction, ix)
+            }
+        }
+    }
 }
 
+impl IntcodeError {
+    // Address of the instruction that failed.
+    pub fn ix(&self) -> usize {
+        match *self {
+            IntcodeError::UnknownOpcode { ix, .. }
+            | IntcodeError::InvalidParamMode { ix, .. }
+            | IntcodeError::NegativeAddress { ix, .. }
+            | IntcodeError::JumpOutOfRange { ix, .. }
+            | IntcodeError::MemoryLimit { ix, .. }
+            | IntcodeError::Overflow { ix, .. } => ix,
+        }
+    }
+}
+
+impl std::error::Error for IntcodeError {}
+
+// What went wrong while decoding, before we know which instruction it belongs to.
 #[derive(Debug)]
-enum ParamMode {
+enum Fault {
+    UnknownOpcode,
+    InvalidParamMode(i64),
+    NegativeAddress(i64),
+    MemoryLimit(usize),
+    Overflow,
+}
+
+impl Fault {
+    fn at(self, ix: usize, instruction: i64) -> IntcodeError {
+        match self {
+            Fault::UnknownOpcode => IntcodeError::UnknownOpcode { ix, instruction },
+            Fault::InvalidParamMode(mode) => IntcodeError::InvalidParamMode {
+                ix,
+                instruction,
+                mode,
+            },
+            Fault::NegativeAddress(address) => IntcodeError::NegativeAddress {
+                ix,
+                instruction,
+                address,
+            },
+            Fault::MemoryLimit(address) => IntcodeError::MemoryLimit {
+                ix,
+                instruction,
+                address,
+            },
+            Fault::Overflow => IntcodeError::Overflow { ix, instruction },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamMode {
     Immediate,
     Position,
     Relative,
 }
 
+impl ParamMode {
+    pub fn from_digit(n: i64) -> Option<Self> {
+        match n {
+            2 => Some(ParamMode::Relative),
+            1 => Some(ParamMode::Immediate),
+            0 => Some(ParamMode::Position),
+            _ => None,
+        }
+    }
+
+    pub fn digit(self) -> i64 {
+        match self {
+            ParamMode::Position => 0,
+            ParamMode::Immediate => 1,
+            ParamMode::Relative => 2,
+        }
+    }
+}
+
 #[derive(Debug)]
 struct ParamModes {
     n: i64,
@@ -22,15 +259,13 @@ impl ParamModes {
     pub fn new(n: i64) -> Self {
         Self { n }
     }
-    pub fn next(&mut self) -> ParamMode {
-        let mode = match self.n % 10 {
-            2 => ParamMode::Relative,
-            1 => ParamMode::Immediate,
-            _ => ParamMode::Position,
-        };
+    #[inline(always)]
+    pub fn next(&mut self) -> Result<ParamMode, Fault> {
+        let digit = self.n % 10;
+        let mode = ParamMode::from_digit(digit).ok_or(Fault::InvalidParamMode(digit))?;
         // println!("next mode({}): {:?}", self.n, mode);
-        self.n = self.n / 10;
-        mode
+        self.n /= 10;
+        Ok(mode)
     }
 }
 
@@ -40,176 +275,1771 @@ enum Operation {
     Multiply(i64, i64, usize),
     Input(usize),
     Output(i64),
-    JumpTrue(i64, usize),
-    JumpFalse(i64, usize),
+    JumpTrue(i64, i64),
+    JumpFalse(i64, i64),
     LessThan(i64, i64, usize),
     EqualTo(i64, i64, usize),
     RelativeBase(i64),
     Exit,
+    Move(i64, usize),
+    LessThanJump(i64, i64, usize),
+    EqualToJump(i64, i64, usize),
+    // An opcode added by the dialect, with its resolved parameters.
+    Extension(i64, [i64; 3], usize),
+}
+
+impl Operation {
+    fn operands(&self) -> Vec<i64> {
+        match *self {
+            Operation::Add(a, b, t)
+            | Operation::Multiply(a, b, t)
+            | Operation::LessThan(a, b, t)
+            | Operation::EqualTo(a, b, t)
+            | Operation::LessThanJump(a, b, t)
+            | Operation::EqualToJump(a, b, t) => vec![a, b, t as i64],
+            Operation::Move(a, t) => vec![a, t as i64],
+            Operation::Input(t) => vec![t as i64],
+            Operation::Output(a) | Operation::RelativeBase(a) => vec![a],
+            Operation::JumpTrue(a, i) | Operation::JumpFalse(a, i) => vec![a, i],
+            Operation::Exit => vec![],
+            Operation::Extension(_, values, len) => values[..len].to_vec(),
+        }
+    }
+}
+
+const MAX_INSTRUCTION_LEN: usize = 4;
+
+// An instruction as it sits in memory, before any parameter is resolved.
+#[derive(Debug, Clone, Copy)]
+struct Decoded {
+    instruction: i64,
+    code: i64,
+    params: &'static [ParamKind],
+    extension: bool,
+    modes: [ParamMode; 3],
+    args: [i64; 3],
+}
+
+impl Decoded {
+    fn len(&self) -> usize {
+        1 + self.params.len()
+    }
+
+    fn is_jump(&self) -> bool {
+        !self.extension && (self.code == 5 || self.code == 6)
+    }
+}
+
+// An instruction as `run_plain` sees it, however it was fetched. Anything that
+// would fault, or reads or writes outside memory, comes back as `None` and is
+// left for `step` to deal with.
+trait Plain: Copy {
+    fn code(&self) -> i64;
+
+    fn len(&self) -> usize;
+
+    // The address parameter `i` refers to, if it's inside `memory`.
+    fn address(&self, i: usize, memory: &[i64], relative_base: i64) -> Option<usize>;
+
+    fn value(&self, i: usize, memory: &[i64], relative_base: i64) -> Option<i64>;
+
+    // Where a JT or JF at `ix` goes, if it doesn't fault.
+    #[inline(always)]
+    fn jump(&self, ix: usize, memory: &[i64], relative_base: i64) -> Option<usize> {
+        let condition = self.value(0, memory, relative_base)?;
+        let target = self.value(1, memory, relative_base)?;
+        if (condition != 0) != (self.code() == 5) {
+            return Some(ix + self.len());
+        }
+        // A negative target is cast to one far past the end.
+        Some(target as usize).filter(|&target| target < memory.len())
+    }
+}
+
+impl Plain for Decoded {
+    #[inline(always)]
+    fn code(&self) -> i64 {
+        self.code
+    }
+
+    #[inline(always)]
+    fn len(&self) -> usize {
+        Decoded::len(self)
+    }
+
+    #[inline(always)]
+    fn address(&self, i: usize, memory: &[i64], relative_base: i64) -> Option<usize> {
+        let address = match self.modes[i] {
+            ParamMode::Relative => relative_base.checked_add(self.args[i])?,
+            _ => self.args[i],
+        };
+        to_address(address)
+            .ok()
+            .filter(|&address| address < memory.len())
+    }
+
+    #[inline(always)]
+    fn value(&self, i: usize, memory: &[i64], relative_base: i64) -> Option<i64> {
+        match self.modes[i] {
+            ParamMode::Immediate => Some(self.args[i]),
+            _ => Some(memory[self.address(i, memory, relative_base)?]),
+        }
+    }
+}
+
+// An instruction the interpreter reads straight out of memory. Building a
+// `Decoded` for every instruction executed costs more than executing it, so
+// each parameter's mode is picked out only when the parameter is used, the way
+// `resolve` would see it.
+#[derive(Clone, Copy)]
+struct Raw {
+    ix: usize,
+    // The instruction's mode digits.
+    modes_word: u64,
+    code: i64,
+    len: usize,
+    // The dialect's allowed modes, by digit.
+    modes: [bool; 3],
+}
+
+impl Raw {
+    #[inline(always)]
+    fn fetch(memory: &[i64], dialect: &Dialect, ix: usize) -> Option<Self> {
+        // Anything not positive has no valid opcode.
+        let instruction = *memory.get(ix)?;
+        if instruction <= 0 {
+            return None;
+        }
+        let instruction = instruction as u64;
+        let code = (instruction % 100) as i64;
+        let len = dialect.builtin_len(code)?;
+        // One that runs off the end reads zeros there, which `step` handles.
+        if ix + len > memory.len() {
+            return None;
+        }
+        Some(Self {
+            ix,
+            modes_word: instruction / 100,
+            code,
+            len,
+            modes: dialect.modes(),
+        })
+    }
+
+    #[inline(always)]
+    fn arg(&self, i: usize, memory: &[i64]) -> i64 {
+        memory[self.ix + 1 + i]
+    }
+
+    #[inline(always)]
+    fn digit(&self, i: usize) -> u64 {
+        self.modes_word / [1, 10, 100][i] % 10
+    }
+}
+
+impl Plain for Raw {
+    #[inline(always)]
+    fn code(&self) -> i64 {
+        self.code
+    }
+
+    #[inline(always)]
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    // A negative address is cast to one far past the end of memory, so it
+    // needs no check of its own.
+    #[inline(always)]
+    fn address(&self, i: usize, memory: &[i64], relative_base: i64) -> Option<usize> {
+        let arg = self.arg(i, memory);
+        let address = match self.digit(i) {
+            0 if self.modes[0] => arg,
+            2 if self.modes[2] => relative_base.checked_add(arg)?,
+            _ => return None,
+        };
+        Some(address as usize).filter(|&address| address < memory.len())
+    }
+
+    #[inline(always)]
+    fn value(&self, i: usize, memory: &[i64], relative_base: i64) -> Option<i64> {
+        let arg = self.arg(i, memory);
+        let address = match self.digit(i) {
+            0 if self.modes[0] => arg,
+            1 if self.modes[1] => return Some(arg),
+            2 if self.modes[2] => relative_base.checked_add(arg)?,
+            _ => return None,
+        };
+        memory.get(address as usize).cloned()
+    }
+}
+
+// How instructions are decoded. `Cached` decodes each address once and keeps
+// the result until something writes over it; writes made straight to `memory`
+// rather than through `poke` aren't seen, so call `clear_cache` after those.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Engine {
+    Interpreter,
+    Cached,
+}
+
+// One executed instruction. Operands are resolved: the values read for input
+// parameters and the target address for output parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    pub ix: usize,
+    pub instruction: i64,
+    pub operands: Vec<i64>,
+    pub writes: Vec<(usize, i64)>,
+    pub relative_base: i64,
+}
+
+impl TraceEvent {
+    pub fn mnemonic(&self) -> &'static str {
+        opcode_info(self.instruction % 100).map_or("???", |info| info.mnemonic)
+    }
+}
+
+pub trait Tracer {
+    fn record(&mut self, event: &TraceEvent);
+}
+
+impl fmt::Debug for dyn Tracer + Send {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Tracer")
+    }
+}
+
+impl<T: Tracer> Tracer for Arc<Mutex<T>> {
+    fn record(&mut self, event: &TraceEvent) {
+        self.lock().unwrap().record(event);
+    }
+}
+
+impl Tracer for Vec<TraceEvent> {
+    fn record(&mut self, event: &TraceEvent) {
+        self.push(event.clone());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamKind {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpcodeInfo {
+    pub code: i64,
+    pub mnemonic: &'static str,
+    pub params: &'static [ParamKind],
+}
+
+use ParamKind::{Read, Write};
+
+pub const OPCODES: &[OpcodeInfo] = &[
+    OpcodeInfo {
+        code: 1,
+        mnemonic: "ADD",
+        params: &[Read, Read, Write],
+    },
+    OpcodeInfo {
+        code: 2,
+        mnemonic: "MUL",
+        params: &[Read, Read, Write],
+    },
+    OpcodeInfo {
+        code: 3,
+        mnemonic: "IN",
+        params: &[Write],
+    },
+    OpcodeInfo {
+        code: 4,
+        mnemonic: "OUT",
+        params: &[Read],
+    },
+    OpcodeInfo {
+        code: 5,
+        mnemonic: "JT",
+        params: &[Read, Read],
+    },
+    OpcodeInfo {
+        code: 6,
+        mnemonic: "JF",
+        params: &[Read, Read],
+    },
+    OpcodeInfo {
+        code: 7,
+        mnemonic: "LT",
+        params: &[Read, Read, Write],
+    },
+    OpcodeInfo {
+        code: 8,
+        mnemonic: "EQ",
+        params: &[Read, Read, Write],
+    },
+    OpcodeInfo {
+        code: 9,
+        mnemonic: "ARB",
+        params: &[Read],
+    },
+    OpcodeInfo {
+        code: 99,
+        mnemonic: "HLT",
+        params: &[],
+    },
+];
+
+// Super-instructions for optimized programs. Each has the shape of an
+// instruction it stands in for, so nothing around it moves. MOV copies its
+// first parameter to its third and ignores the second; LTJ and EQJ compare like
+// LT and EQ, then take the JT or JF after them (if that's what follows) as part
+// of the same step.
+pub const FUSED_OPCODES: &[OpcodeInfo] = &[
+    OpcodeInfo {
+        code: 20,
+        mnemonic: "MOV",
+        params: &[Read, Read, Write],
+    },
+    OpcodeInfo {
+        code: 21,
+        mnemonic: "LTJ",
+        params: &[Read, Read, Write],
+    },
+    OpcodeInfo {
+        code: 22,
+        mnemonic: "EQJ",
+        params: &[Read, Read, Write],
+    },
+];
+
+pub fn opcode_info(code: i64) -> Option<&'static OpcodeInfo> {
+    OPCODES.iter().find(|op| op.code == code)
+}
+
+// Decodes the instruction at the start of `words`; any words past the end read
+// as zero.
+#[inline(always)]
+fn decode(words: &[i64], dialect: &Dialect) -> Result<Decoded, Fault> {
+    let word = |i: usize| words.get(i).cloned().unwrap_or(0);
+    let instruction = word(0);
+    let code = instruction % 100;
+    let info = dialect.info(code).ok_or(Fault::UnknownOpcode)?;
+    let mut modes = ParamModes::new(instruction / 100);
+    let mut decoded = Decoded {
+        instruction,
+        code,
+        params: info.params,
+        extension: dialect.is_extension(code),
+        modes: [ParamMode::Position; 3],
+        args: [0; 3],
+    };
+    for (i, kind) in info.params.iter().enumerate() {
+        let mode = modes.next()?;
+        if !dialect.allows(mode) {
+            return Err(Fault::InvalidParamMode(mode.digit()));
+        }
+        if *kind == ParamKind::Write && mode == ParamMode::Immediate {
+            return Err(Fault::InvalidParamMode(1));
+        }
+        decoded.modes[i] = mode;
+        decoded.args[i] = word(1 + i);
+    }
+    Ok(decoded)
+}
+
+// Drops any cached instruction that covers `address`.
+fn invalidate(decoded: &mut [Option<Decoded>], address: usize) {
+    let first = address.saturating_sub(MAX_INSTRUCTION_LEN - 1);
+    if first >= decoded.len() {
+        return;
+    }
+    for start in first..=address {
+        if let Some(slot) = decoded.get_mut(start) {
+            if slot.is_some_and(|decoded| start + decoded.len() > address) {
+                *slot = None;
+            }
+        }
+    }
+}
+
+fn to_address(address: i64) -> Result<usize, Fault> {
+    if address < 0 {
+        Err(Fault::NegativeAddress(address))
+    } else {
+        Ok(address as usize)
+    }
 }
 
 impl Computer {
     pub fn new(memory: Vec<i64>) -> Self {
+        Self::with_memory(memory)
+    }
+
+    pub fn with_memory<M: Memory + Send + 'static>(memory: M) -> Self {
         Self {
-            memory,
+            memory: Box::new(memory),
+            memory_limit: None,
             ix: 0,
-            inputs: Vec::new(),
+            inputs: VecDeque::new(),
             relative_base: 0,
+            op_ix: 0,
+            watchpoints: Vec::new(),
+            next_watch_id: 0,
+            hits: VecDeque::new(),
+            tracer: None,
+            traced_writes: Vec::new(),
+            step_limit: None,
+            cycles: 0,
+            opcode_counts: Box::new([0; 100]),
+            arithmetic: Arithmetic::Native,
+            wide: BTreeMap::new(),
+            param_addresses: Vec::new(),
+            engine: Engine::Interpreter,
+            decoded: Vec::new(),
+            profile: None,
+            history: None,
+            dialect: Dialect::default(),
+            coverage: None,
+        }
+    }
+
+    // Memory comes back dense unless it's mostly empty, as after a far write.
+    pub fn from_snapshot(snapshot: Snapshot) -> Self {
+        let mut computer = if snapshot.len > 2 * snapshot.memory.len() + 1024 {
+            Self::with_memory(PagedMemory::default())
+        } else {
+            Self::new(Vec::new())
+        };
+        computer.restore(&snapshot);
+        computer
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            len: self.memory.len(),
+            memory: self.memory.nonzero().collect(),
+            ix: self.ix,
+            relative_base: self.relative_base,
+            inputs: self.inputs.iter().cloned().collect(),
+        }
+    }
+
+    // Watchpoints and the tracer aren't machine state, so they stay as they are.
+    // Snapshots only hold 64-bit words, so any big-mode values are lost.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        // Only the words that differ need their cached instructions dropped, so
+        // restarting a program with a few words patched stays cheap. A word
+        // that differs is non-zero on at least one side.
+        if !self.decoded.is_empty() {
+            let stale: Vec<usize> = self
+                .memory
+                .nonzero()
+                .filter(|&(address, value)| snapshot.get(address) != value)
+                .map(|(address, _)| address)
+                .chain(
+                    snapshot
+                        .memory
+                        .iter()
+                        .filter(|&&(address, value)| self.memory.get(address) != value)
+                        .map(|&(address, _)| address),
+                )
+                .collect();
+            for address in stale {
+                invalidate(&mut self.decoded, address);
+            }
+        }
+        self.memory.load_sparse(snapshot.len, &snapshot.memory);
+        self.wide.clear();
+        self.ix = snapshot.ix;
+        self.relative_base = snapshot.relative_base;
+        self.inputs = snapshot.inputs.iter().cloned().collect();
+        self.hits.clear();
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
         }
     }
 
     pub fn add_input(&mut self, input: i64) {
         // println!("add_input({})", input);
-        self.inputs.push(input);
+        self.inputs.push_back(input);
+    }
+
+    // Pausing watchpoints stop `run` with `State::Watchpoint` once the instruction
+    // that touched the range has finished; callbacks fire without stopping.
+    pub fn watch(&mut self, range: Range<usize>, access: Access) -> usize {
+        self.add_watchpoint(range, access, None)
+    }
+
+    pub fn watch_with<F: FnMut(&Hit) + Send + 'static>(
+        &mut self,
+        range: Range<usize>,
+        access: Access,
+        callback: F,
+    ) -> usize {
+        self.add_watchpoint(range, access, Some(Box::new(callback)))
+    }
+
+    // Caps the total number of instructions executed; once reached, `run` and
+    // `step` return `State::BudgetExhausted` until the limit is raised.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit;
+    }
+
+    pub fn step_limit(&self) -> Option<u64> {
+        self.step_limit
+    }
+
+    // Caps the words the memory backend may hold. An instruction whose write
+    // would go past it fails with `IntcodeError::MemoryLimit` before running.
+    pub fn set_memory_limit(&mut self, words: Option<usize>) {
+        self.memory_limit = words;
+    }
+
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.arithmetic = arithmetic;
+    }
+
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+        self.clear_cache();
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    pub fn clear_cache(&mut self) {
+        self.decoded.clear();
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn opcode_counts(&self) -> BTreeMap<i64, u64> {
+        (0..100)
+            .filter(|&op| self.opcode_counts[op] > 0)
+            .map(|op| (op as i64, self.opcode_counts[op]))
+            .collect()
+    }
+
+    // Opcodes outside the dialect fail as unknown.
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+        self.clear_cache();
+    }
+
+    pub fn dialect(&self) -> &Dialect {
+        &self.dialect
+    }
+
+    // Turning profiling on starts a fresh profile; turning it off drops it.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = if enabled { Some(Profile::new()) } else { None };
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    // Turning coverage on starts afresh; turning it off drops what was gathered.
+    pub fn set_coverage(&mut self, enabled: bool) {
+        self.coverage = if enabled { Some(Coverage::new()) } else { None };
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    // Keeps an undo log of the last `steps` instructions so the machine can be
+    // stepped backwards. None turns it off and drops the log.
+    pub fn set_history(&mut self, steps: Option<usize>) {
+        self.history = steps.map(History::new);
+    }
+
+    // How many instructions can currently be undone.
+    pub fn undoable(&self) -> usize {
+        self.history.as_ref().map_or(0, |history| history.len())
+    }
+
+    fn undo(&mut self) -> bool {
+        let undo = match self.history.as_mut().and_then(|history| history.pop()) {
+            Some(undo) => undo,
+            None => return false,
+        };
+        for (address, old, wide) in undo.writes.into_iter().rev() {
+            self.store(address, old);
+            if let Some(wide) = wide {
+                self.wide.insert(address, wide);
+            }
+        }
+        if let Some(input) = undo.input {
+            self.inputs.push_front(input);
+        }
+        self.ix = undo.ix;
+        self.relative_base = undo.relative_base;
+        self.cycles -= 1;
+        self.opcode_counts[(undo.instruction % 100) as usize] -= 1;
+        self.hits.clear();
+        true
+    }
+
+    // Undoes up to `steps` instructions, returning how many were undone. Inputs
+    // they consumed go back on the front of the queue, so running forward again
+    // replays the same execution. Memory that grew stays grown, holding zeros.
+    pub fn step_back(&mut self, steps: usize) -> usize {
+        let mut undone = 0;
+        while undone < steps && self.undo() {
+            undone += 1;
+        }
+        undone
+    }
+
+    // Rewinds to just before the most recent output, so the next `run` yields it
+    // again. Does nothing and returns false if it's no longer in the log.
+    pub fn rewind_to_output(&mut self) -> bool {
+        match self
+            .history
+            .as_ref()
+            .and_then(|history| history.distance_to(4))
+        {
+            Some(steps) => self.step_back(steps) == steps,
+            None => false,
+        }
+    }
+
+    // Rewinds to just before the most recent input was read and takes that input
+    // back off the queue, so a different one can be given in its place.
+    pub fn rewind_to_input(&mut self) -> Option<i64> {
+        let steps = self.history.as_ref()?.distance_to(3)?;
+        self.step_back(steps);
+        self.inputs.pop_front()
+    }
+
+    pub fn set_tracer<T: Tracer + Send + 'static>(&mut self, tracer: T) {
+        self.tracer = Some(Box::new(tracer));
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer + Send>> {
+        self.tracer.take()
+    }
+
+    pub fn unwatch(&mut self, id: usize) -> bool {
+        let before = self.watchpoints.len();
+        self.watchpoints.retain(|w| w.id != id);
+        self.watchpoints.len() != before
+    }
+
+    fn add_watchpoint(
+        &mut self,
+        range: Range<usize>,
+        access: Access,
+        callback: Option<WatchCallback>,
+    ) -> usize {
+        let id = self.next_watch_id;
+        self.next_watch_id += 1;
+        self.watchpoints.push(Watchpoint {
+            id,
+            range,
+            access,
+            callback,
+        });
+        id
+    }
+
+    fn notify(&mut self, address: usize, access: Access, value: i64) {
+        for watchpoint in self.watchpoints.iter_mut() {
+            if !watchpoint.range.contains(&address) || !watchpoint.access.covers(access) {
+                continue;
+            }
+            let hit = Hit {
+                id: watchpoint.id,
+                ix: self.op_ix,
+                address,
+                access,
+                value,
+            };
+            match watchpoint.callback.as_mut() {
+                Some(callback) => callback(&hit),
+                None => self.hits.push_back(hit),
+            }
+        }
+    }
+
+    fn store(&mut self, ix: usize, val: i64) {
+        if !self.wide.is_empty() {
+            self.wide.remove(&ix);
+        }
+        if !self.decoded.is_empty() {
+            invalidate(&mut self.decoded, ix);
+        }
+        self.memory.set(ix, val);
+    }
+
+    fn write_wide(&mut self, ix: usize, val: BigInt) {
+        self.write_mem(ix, val.saturate());
+        if val.to_i64().is_none() {
+            self.wide.insert(ix, val);
+        }
+    }
+
+    fn is_wide(&self, param: usize) -> bool {
+        match self.param_addresses.get(param) {
+            Some(Some(address)) => self.wide.contains_key(address),
+            _ => false,
+        }
+    }
+
+    fn wide_param(&self, param: usize, narrow: i64) -> BigInt {
+        match self.param_addresses.get(param) {
+            Some(Some(address)) if self.wide.contains_key(address) => self.wide[address].clone(),
+            _ => BigInt::from(narrow),
+        }
+    }
+
+    // ADD and MUL under the selected arithmetic mode. `Err` means overflow.
+    fn arithmetic_op(
+        &mut self,
+        t: usize,
+        a: i64,
+        b: i64,
+        native: fn(i64, i64) -> i64,
+        checked: fn(i64, i64) -> Option<i64>,
+        big: fn(&BigInt, &BigInt) -> BigInt,
+    ) -> Result<(), ()> {
+        match self.arithmetic {
+            Arithmetic::Native => self.write_mem(t, native(a, b)),
+            Arithmetic::Checked => self.write_mem(t, checked(a, b).ok_or(())?),
+            Arithmetic::Big => {
+                let result = big(&self.wide_param(0, a), &self.wide_param(1, b));
+                self.write_wide(t, result);
+            }
+        }
+        Ok(())
+    }
+
+    fn compare(&self, a: i64, b: i64) -> std::cmp::Ordering {
+        if self.is_wide(0) || self.is_wide(1) {
+            self.wide_param(0, a).cmp(&self.wide_param(1, b))
+        } else {
+            a.cmp(&b)
+        }
     }
 
     fn write_mem(&mut self, ix: usize, val: i64) {
         // println!("write({}) to {}", val, ix);
-        if ix >= self.memory.len() {
-            self.memory
-                .extend(std::iter::repeat(0).take(ix - self.memory.len() + 1));
+        if !self.watchpoints.is_empty() {
+            self.notify(ix, Access::Write, val);
+        }
+        if self.tracer.is_some() {
+            self.traced_writes.push((ix, val));
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.write(ix);
+        }
+        if let Some(history) = self.history.as_mut() {
+            let old = self.memory.get(ix);
+            history.writes.push((ix, old, self.wide.get(&ix).cloned()));
         }
-        self.memory[ix] = val;
+        self.store(ix, val);
     }
 
     fn read_mem(&mut self, ix: usize) -> i64 {
         // println!("read_mem({})...", ix);
-        if ix >= self.memory.len() {
-            0
-        } else {
-            self.memory[ix]
+        let val = self.peek(ix);
+        if !self.watchpoints.is_empty() {
+            self.notify(ix, Access::Read, val);
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.read(ix);
         }
+        val
     }
 
-    fn read_next(&mut self) -> i64 {
-        // println!("read_next()...");
-        let mem = self.read_mem(self.ix);
-        // println!("read_next(): {} = {}", self.ix, mem);
-        self.ix += 1;
-        return mem;
+    // Static decoding only: nothing here depends on the relative base or on
+    // memory other than the instruction's own words, which is what makes it
+    // safe to cache.
+    fn fetch(&self, ix: usize) -> Result<Decoded, Fault> {
+        let mut words = [0; MAX_INSTRUCTION_LEN];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = self.peek(ix + i);
+        }
+        decode(&words, &self.dialect)
     }
 
-    fn read_next_op(&mut self) -> Operation {
-        let next = self.read_next();
-        let mut modes = ParamModes::new(next / 100);
-        // println!("next,modes: {},{:?}", next, modes);
-        match next % 100 {
-            1 => Operation::Add(
-                self.read_next_param(&mut modes),
-                self.read_next_param(&mut modes),
-                self.read_next_address(&mut modes),
-            ),
-            2 => Operation::Multiply(
-                self.read_next_param(&mut modes),
-                self.read_next_param(&mut modes),
-                self.read_next_address(&mut modes),
-            ),
-            3 => Operation::Input(self.read_next_address(&mut modes)),
-            4 => Operation::Output(self.read_next_param(&mut modes)),
-            5 => Operation::JumpTrue(
-                self.read_next_param(&mut modes),
-                self.read_next_param(&mut modes) as usize,
-            ),
-            6 => Operation::JumpFalse(
-                self.read_next_param(&mut modes),
-                self.read_next_param(&mut modes) as usize,
-            ),
-            7 => Operation::LessThan(
-                self.read_next_param(&mut modes),
-                self.read_next_param(&mut modes),
-                self.read_next_address(&mut modes),
-            ),
-            8 => Operation::EqualTo(
-                self.read_next_param(&mut modes),
-                self.read_next_param(&mut modes),
-                self.read_next_address(&mut modes),
-            ),
-            9 => Operation::RelativeBase(self.read_next_param(&mut modes)),
-            _ => Operation::Exit,
+    fn fetch_cached(&mut self, ix: usize) -> Result<Decoded, Fault> {
+        if let Some(Some(decoded)) = self.decoded.get(ix) {
+            return Ok(*decoded);
         }
+        let decoded = self.fetch(ix)?;
+        if ix >= self.decoded.len() {
+            self.decoded.resize(ix + 1, None);
+        }
+        self.decoded[ix] = Some(decoded);
+        Ok(decoded)
     }
 
-    fn read_next_address(&mut self, mode: &mut ParamModes) -> usize {
-        let address = self.ix;
-        let val = self.read_next() as usize;
-        // println!("read_next_address({:?}) - {},{}", mode, address, val);
-        // println!("{:?}", self);
-        match mode.next() {
-            ParamMode::Immediate => panic!("immediate mode not allowed"),
-            ParamMode::Position => val,
-            ParamMode::Relative => (val as i64 + self.relative_base) as usize,
+    fn resolve(&mut self, decoded: &Decoded) -> Result<Operation, Fault> {
+        let mut values = [0; 3];
+        for (i, kind) in decoded.params.iter().enumerate() {
+            values[i] = match kind {
+                ParamKind::Read => self.resolve_param(decoded.modes[i], decoded.args[i])?,
+                ParamKind::Write => self.resolve_address(decoded.modes[i], decoded.args[i])? as i64,
+            };
+        }
+        let code = decoded.code;
+        if decoded.extension {
+            return Ok(Operation::Extension(code, values, decoded.params.len()));
+        }
+        let [a, b, t] = values;
+        let op = match code {
+            1 => Operation::Add(a, b, t as usize),
+            2 => Operation::Multiply(a, b, t as usize),
+            3 => Operation::Input(a as usize),
+            4 => Operation::Output(a),
+            5 => Operation::JumpTrue(a, b),
+            6 => Operation::JumpFalse(a, b),
+            7 => Operation::LessThan(a, b, t as usize),
+            8 => Operation::EqualTo(a, b, t as usize),
+            9 => Operation::RelativeBase(a),
+            99 => Operation::Exit,
+            20 => Operation::Move(a, t as usize),
+            21 => Operation::LessThanJump(a, b, t as usize),
+            22 => Operation::EqualToJump(a, b, t as usize),
+            _ => return Err(Fault::UnknownOpcode),
+        };
+        Ok(op)
+    }
+
+    // Whatever the arithmetic mode, an offset that takes the address past i64
+    // is an overflow rather than a panic or a wrapped address.
+    fn relative_address(&self, offset: i64) -> Result<usize, Fault> {
+        to_address(
+            self.relative_base
+                .checked_add(offset)
+                .ok_or(Fault::Overflow)?,
+        )
+    }
+
+    fn resolve_address(&mut self, mode: ParamMode, val: i64) -> Result<usize, Fault> {
+        let address = match mode {
+            ParamMode::Immediate => return Err(Fault::InvalidParamMode(1)),
+            ParamMode::Position => to_address(val)?,
+            ParamMode::Relative => self.relative_address(val)?,
+        };
+        match self.memory_limit {
+            Some(limit) if self.memory.footprint_after(address) > limit => {
+                Err(Fault::MemoryLimit(address))
+            }
+            _ => Ok(address),
+        }
+    }
+
+    fn resolve_param(&mut self, mode: ParamMode, val: i64) -> Result<i64, Fault> {
+        let address = match mode {
+            ParamMode::Immediate => None,
+            ParamMode::Position => Some(to_address(val)?),
+            ParamMode::Relative => Some(self.relative_address(val)?),
+        };
+        if self.arithmetic == Arithmetic::Big {
+            self.param_addresses.push(address);
         }
+        Ok(match address {
+            Some(address) => self.read_mem(address),
+            None => val,
+        })
     }
 
-    fn read_next_param(&mut self, mode: &mut ParamModes) -> i64 {
-        let val = self.read_next();
-        // println!("read_next_param({:?}) - {}", mode, val);
-        match mode.next() {
-            ParamMode::Immediate => val,
-            ParamMode::Position => self.read_mem(val as usize),
-            ParamMode::Relative => self.read_mem((val + self.relative_base) as usize),
+    fn jump(&mut self, target: i64) -> Result<(), i64> {
+        if target < 0 || target as usize >= self.memory.len() {
+            return Err(target);
         }
+        self.ix = target as usize;
+        Ok(())
+    }
+
+    pub fn ix(&self) -> usize {
+        self.ix
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    pub fn inputs(&self) -> &VecDeque<i64> {
+        &self.inputs
+    }
+
+    pub fn peek(&self, ix: usize) -> i64 {
+        self.memory.get(ix)
+    }
+
+    pub fn poke(&mut self, ix: usize, val: i64) {
+        self.store(ix, val);
+    }
+
+    // A write by the instruction being executed, which unlike `poke` is seen by
+    // watchpoints, the tracer and the undo log. For extension opcodes.
+    pub fn write(&mut self, ix: usize, val: i64) {
+        self.write_mem(ix, val);
     }
 
-    pub fn run(&mut self) -> State {
+    pub fn run(&mut self) -> Result<State, IntcodeError> {
         loop {
-            // println!("{:?}", self);
-            let next_op = self.read_next_op();
-            // println!("{:?}", next_op);
-            match next_op {
-                Operation::Add(a, b, t) => self.write_mem(t, a + b),
-                Operation::Multiply(a, b, t) => self.write_mem(t, a * b),
-                Operation::Input(t) => {
-                    if self.inputs.is_empty() {
-                        self.ix -= 2;
-                        return State::AwaitingInput;
-                    } else {
-                        let val = self.inputs.remove(0).to_owned();
-                        self.write_mem(t, val);
+            if !self.instrumented() {
+                if let Some(state) = self.run_plain() {
+                    return Ok(state);
+                }
+            }
+            if let Some(state) = self.step()? {
+                return Ok(state);
+            }
+        }
+    }
+
+    // Whether anything needs to see each instruction as `step` executes it.
+    fn instrumented(&self) -> bool {
+        !self.watchpoints.is_empty()
+            || !self.hits.is_empty()
+            || self.tracer.is_some()
+            || self.profile.is_some()
+            || self.history.is_some()
+            || self.coverage.is_some()
+            || self.arithmetic == Arithmetic::Big
+            || !self.wide.is_empty()
+    }
+
+    // Runs instructions straight out of dense memory for as long as nothing
+    // unusual happens, returning the state if it reaches one `run` stops at.
+    // Otherwise the machine is left on the first instruction that needs
+    // `step`: extensions, faults, and anything touching memory past the end.
+    fn run_plain(&mut self) -> Option<State> {
+        match self.engine {
+            Engine::Interpreter => {
+                self.run_plain_with(|memory, dialect, _, ix| Raw::fetch(memory, dialect, ix))
+            }
+            Engine::Cached => self.run_plain_with(|_, _, cache, ix| match cache.get(ix) {
+                Some(Some(decoded)) if !decoded.extension => Some(*decoded),
+                _ => None,
+            }),
+        }
+    }
+
+    #[inline(always)]
+    fn run_plain_with<P, F>(&mut self, fetch: F) -> Option<State>
+    where
+        P: Plain,
+        F: Fn(&[i64], &Dialect, &[Option<Decoded>], usize) -> Option<P>,
+    {
+        let memory = self.memory.dense_mut()?;
+        let dialect = &self.dialect;
+        let inputs = &mut self.inputs;
+        let cache = &mut self.decoded;
+        let opcode_counts = &mut self.opcode_counts;
+        let checked = self.arithmetic == Arithmetic::Checked;
+        let limit = self.step_limit.unwrap_or(u64::MAX);
+        // Writes here stay inside memory, so the footprint never grows; one
+        // already past the memory limit leaves everything to `step`, which
+        // refuses the writes.
+        if self.memory_limit.is_some_and(|words| memory.len() > words) {
+            return None;
+        }
+        let mut ix = self.ix;
+        let mut relative_base = self.relative_base;
+        let mut cycles = self.cycles;
+        let mut state = None;
+        while cycles < limit {
+            let instruction = match fetch(memory, dialect, cache, ix) {
+                Some(instruction) => instruction,
+                None => break,
+            };
+            let code = instruction.code();
+            let value = |i: usize| instruction.value(i, memory, relative_base);
+            let address = |i: usize| instruction.address(i, memory, relative_base);
+            let mut next = ix + instruction.len();
+            match code {
+                // MOV ignores its middle parameter, but it still has to be valid.
+                1 | 2 | 7 | 8 | 20 | 21 | 22 => {
+                    let (a, b, t) = match (value(0), value(1), address(2)) {
+                        (Some(a), Some(b), Some(t)) => (a, b, t),
+                        _ => break,
+                    };
+                    let result = match code {
+                        1 if checked => match a.checked_add(b) {
+                            Some(result) => result,
+                            None => break,
+                        },
+                        1 => a + b,
+                        2 if checked => match a.checked_mul(b) {
+                            Some(result) => result,
+                            None => break,
+                        },
+                        2 => a * b,
+                        7 | 21 => (a < b) as i64,
+                        8 | 22 => (a == b) as i64,
+                        _ => a,
+                    };
+                    let old = std::mem::replace(&mut memory[t], result);
+                    if !cache.is_empty() {
+                        invalidate(cache, t);
+                    }
+                    if code == 21 || code == 22 {
+                        // Whatever follows is fetched after the write, as `step`
+                        // would, and the write undone if it can't be or it's a
+                        // jump that faults.
+                        let target = match fetch(memory, dialect, cache, next) {
+                            Some(jump) if jump.code() == 5 || jump.code() == 6 => {
+                                jump.jump(next, memory, relative_base)
+                            }
+                            Some(_) => Some(next),
+                            None => None,
+                        };
+                        match target {
+                            Some(target) => next = target,
+                            None => {
+                                memory[t] = old;
+                                break;
+                            }
+                        }
                     }
                 }
-                Operation::Output(a) => {
-                    return State::Output(a);
+                5 | 6 => match instruction.jump(ix, memory, relative_base) {
+                    Some(target) => next = target,
+                    None => break,
+                },
+                9 => {
+                    let a = match value(0) {
+                        Some(a) => a,
+                        None => break,
+                    };
+                    relative_base = match relative_base.checked_add(a) {
+                        Some(base) => base,
+                        None => break,
+                    };
                 }
-                Operation::JumpTrue(a, i) => {
-                    if a != 0 {
-                        self.ix = i;
+                3 => {
+                    let t = match address(0) {
+                        Some(t) => t,
+                        None => break,
+                    };
+                    match inputs.pop_front() {
+                        Some(input) => {
+                            memory[t] = input;
+                            if !cache.is_empty() {
+                                invalidate(cache, t);
+                            }
+                        }
+                        None => {
+                            state = Some(State::AwaitingInput);
+                            break;
+                        }
                     }
                 }
-                Operation::JumpFalse(a, i) => {
-                    if a == 0 {
-                        self.ix = i;
-                    }
+                4 => match value(0) {
+                    Some(a) => state = Some(State::Output(a)),
+                    None => break,
+                },
+                99 => {
+                    next = ix;
+                    state = Some(State::Complete);
                 }
-                Operation::LessThan(a, b, t) => {
-                    if (a < b) {
-                        self.write_mem(t, 1);
-                    } else {
-                        self.write_mem(t, 0);
+                _ => break,
+            }
+            ix = next;
+            cycles += 1;
+            opcode_counts[code as usize] += 1;
+            if state.is_some() {
+                break;
+            }
+        }
+        self.ix = ix;
+        self.relative_base = relative_base;
+        self.cycles = cycles;
+        state
+    }
+
+    // The JT or JF after a fused compare, taken as part of the compare's step.
+    // Anything else there is left to run as a step of its own. A fault is
+    // reported at the jump, as it would have been without fusing.
+    fn fused_jump(&mut self) -> Result<Option<(bool, bool)>, IntcodeError> {
+        let ix = self.ix;
+        let decoded = match self.engine {
+            Engine::Interpreter => self.fetch(ix),
+            Engine::Cached => self.fetch_cached(ix),
+        };
+        let decoded = match decoded {
+            Ok(decoded) if decoded.is_jump() => decoded,
+            _ => return Ok(None),
+        };
+        let instruction = decoded.instruction;
+        let fixed_target = decoded.modes[1] == ParamMode::Immediate;
+        self.param_addresses.clear();
+        self.ix = ix + decoded.len();
+        let (condition, target) = match self
+            .resolve(&decoded)
+            .map_err(|fault| fault.at(ix, instruction))?
+        {
+            // Wide values are never zero.
+            Operation::JumpTrue(a, target) => (a != 0 || self.is_wide(0), target),
+            Operation::JumpFalse(a, target) => (a == 0 && !self.is_wide(0), target),
+            op => unreachable!("{:?} isn't a jump", op),
+        };
+        if condition {
+            self.jump(target)
+                .map_err(|target| IntcodeError::JumpOutOfRange {
+                    ix,
+                    instruction,
+                    target,
+                })?;
+        }
+        Ok(Some((condition, fixed_target)))
+    }
+
+    // Executes a single instruction, returning the state if it's one `run` would stop at.
+    // An instruction that fails leaves the machine pointing at it, so running
+    // again reports the same error rather than carrying on past it.
+    pub fn step(&mut self) -> Result<Option<State>, IntcodeError> {
+        let result = self.execute();
+        if let Err(err) = &result {
+            self.ix = err.ix();
+            // Pausing hits from the failed instruction's reads would otherwise
+            // be reported instead of the error next time.
+            self.hits.clear();
+            if let Some(profile) = self.profile.as_mut() {
+                profile.pause();
+            }
+        }
+        result
+    }
+
+    fn execute(&mut self) -> Result<Option<State>, IntcodeError> {
+        // println!("{:?}", self);
+        if let Some(hit) = self.hits.pop_front() {
+            return Ok(Some(State::Watchpoint(hit)));
+        }
+        if self.step_limit.is_some_and(|limit| self.cycles >= limit) {
+            return Ok(Some(State::BudgetExhausted));
+        }
+        if let Some(profile) = self.profile.as_mut() {
+            profile.resume();
+        }
+        let ix = self.ix;
+        let relative_base = self.relative_base;
+        let mut consumed = None;
+        let mut taken = None;
+        self.op_ix = ix;
+        self.param_addresses.clear();
+        if let Some(history) = self.history.as_mut() {
+            history.writes.clear();
+        }
+        let decoded = match self.engine {
+            Engine::Interpreter => self.fetch(ix),
+            Engine::Cached => self.fetch_cached(ix),
+        }
+        .map_err(|fault| fault.at(ix, self.peek(ix)))?;
+        let instruction = decoded.instruction;
+        // Whether a jump goes somewhere fixed, rather than somewhere read
+        // from memory like a return address.
+        let mut fixed_target = decoded.is_jump() && decoded.modes[1] == ParamMode::Immediate;
+        self.ix = ix + decoded.len();
+        let next_op = self
+            .resolve(&decoded)
+            .map_err(|fault| fault.at(ix, instruction))?;
+        let out_of_range = |target| IntcodeError::JumpOutOfRange {
+            ix,
+            instruction,
+            target,
+        };
+        let overflow = |_| IntcodeError::Overflow { ix, instruction };
+        let operands = match self.tracer {
+            Some(_) => next_op.operands(),
+            None => Vec::new(),
+        };
+        // println!("{:?}", next_op);
+        let state = match next_op {
+            Operation::Add(a, b, t) => {
+                self.arithmetic_op(t, a, b, |a, b| a + b, i64::checked_add, |a, b| a + b)
+                    .map_err(overflow)?;
+                None
+            }
+            Operation::Multiply(a, b, t) => {
+                self.arithmetic_op(t, a, b, |a, b| a * b, i64::checked_mul, |a, b| a * b)
+                    .map_err(overflow)?;
+                None
+            }
+            Operation::Input(t) => {
+                if let Some(val) = self.inputs.pop_front() {
+                    consumed = Some(val);
+                    self.write_mem(t, val);
+                    None
+                } else {
+                    self.ix = ix;
+                    if let Some(profile) = self.profile.as_mut() {
+                        profile.pause();
                     }
+                    return Ok(Some(State::AwaitingInput));
                 }
-                Operation::EqualTo(a, b, t) => {
-                    if (a == b) {
-                        self.write_mem(t, 1);
-                    } else {
-                        self.write_mem(t, 0);
-                    }
+            }
+            Operation::Output(a) if self.is_wide(0) => {
+                Some(State::BigOutput(self.wide_param(0, a)))
+            }
+            Operation::Output(a) => Some(State::Output(a)),
+            // Wide values are never zero.
+            Operation::JumpTrue(a, i) => {
+                if a != 0 || self.is_wide(0) {
+                    self.jump(i).map_err(out_of_range)?;
+                    taken = Some(true);
+                } else {
+                    taken = Some(false);
+                }
+                None
+            }
+            Operation::JumpFalse(a, i) => {
+                if a == 0 && !self.is_wide(0) {
+                    self.jump(i).map_err(out_of_range)?;
+                    taken = Some(true);
+                } else {
+                    taken = Some(false);
+                }
+                None
+            }
+            Operation::LessThan(a, b, t) => {
+                if self.compare(a, b).is_lt() {
+                    self.write_mem(t, 1);
+                } else {
+                    self.write_mem(t, 0);
+                }
+                None
+            }
+            Operation::EqualTo(a, b, t) => {
+                if self.compare(a, b).is_eq() {
+                    self.write_mem(t, 1);
+                } else {
+                    self.write_mem(t, 0);
+                }
+                None
+            }
+            // The base is an address, so it overflows the same way whatever
+            // the arithmetic mode.
+            Operation::RelativeBase(a) => {
+                if self.is_wide(0) {
+                    return Err(overflow(()));
                 }
-                Operation::RelativeBase(a) => {
-                    self.relative_base += a;
+                self.relative_base = self
+                    .relative_base
+                    .checked_add(a)
+                    .ok_or(())
+                    .map_err(overflow)?;
+                None
+            }
+            Operation::Exit => {
+                self.ix = ix;
+                Some(State::Complete)
+            }
+            Operation::Move(a, t) => {
+                if self.is_wide(0) {
+                    let value = self.wide_param(0, a);
+                    self.write_wide(t, value);
+                } else {
+                    self.write_mem(t, a);
                 }
-                Operation::Exit => {
-                    return State::Complete;
+                None
+            }
+            Operation::LessThanJump(a, b, t) => {
+                let holds = self.compare(a, b).is_lt();
+                self.write_mem(t, holds as i64);
+                if let Some((jumped, fixed)) = self.fused_jump()? {
+                    taken = Some(jumped);
+                    fixed_target = fixed;
                 }
+                None
             }
+            Operation::EqualToJump(a, b, t) => {
+                let holds = self.compare(a, b).is_eq();
+                self.write_mem(t, holds as i64);
+                if let Some((jumped, fixed)) = self.fused_jump()? {
+                    taken = Some(jumped);
+                    fixed_target = fixed;
+                }
+                None
+            }
+            Operation::Extension(code, values, len) => {
+                let mut extension = self
+                    .dialect
+                    .take_extension(code)
+                    .ok_or_else(|| Fault::UnknownOpcode.at(ix, instruction))?;
+                let state = extension.execute(self, &values[..len]);
+                self.dialect.restore_extension(code, extension);
+                state
+            }
+        };
+        self.cycles += 1;
+        self.opcode_counts[(instruction % 100) as usize] += 1;
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.execute(ix, instruction, taken);
         }
+        if let Some(history) = self.history.as_mut() {
+            let writes = std::mem::take(&mut history.writes);
+            history.push(Undo {
+                ix,
+                relative_base,
+                instruction,
+                writes,
+                input: consumed,
+            });
+        }
+        if let Some(profile) = self.profile.as_mut() {
+            profile.record(ix, instruction, self.ix, fixed_target);
+            if state == Some(State::Complete) {
+                profile.pause();
+            }
+        }
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.record(&TraceEvent {
+                ix,
+                instruction,
+                operands,
+                writes: std::mem::take(&mut self.traced_writes),
+                relative_base: self.relative_base,
+            });
+        }
+        Ok(state.or_else(|| self.hits.pop_front().map(State::Watchpoint)))
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum State {
     Idle,
     AwaitingInput,
     Output(i64),
+    // Only in big arithmetic mode, for values that don't fit in an i64.
+    BigOutput(BigInt),
+    Watchpoint(Hit),
+    BudgetExhausted,
     Complete,
 }
+
+#[test]
+pub fn unknown_opcode() {
+    let mut computer = Computer::new(vec![1, 0, 0, 0, 42, 99]);
+    assert_eq!(
+        computer.run(),
+        Err(IntcodeError::UnknownOpcode {
+            ix: 4,
+            instruction: 42
+        })
+    );
+}
+
+#[test]
+pub fn zero_is_not_a_halt() {
+    let mut computer = Computer::new(vec![0]);
+    assert_eq!(
+        computer.run(),
+        Err(IntcodeError::UnknownOpcode {
+            ix: 0,
+            instruction: 0
+        })
+    );
+}
+
+#[test]
+pub fn immediate_write_address() {
+    let mut computer = Computer::new(vec![11101, 1, 1, 5, 99, 0]);
+    assert_eq!(
+        computer.run(),
+        Err(IntcodeError::InvalidParamMode {
+            ix: 0,
+            instruction: 11101,
+            mode: 1
+        })
+    );
+}
+
+#[test]
+pub fn unknown_param_mode() {
+    let mut computer = Computer::new(vec![104, 1, 304, 1, 99]);
+    assert_eq!(computer.run(), Ok(State::Output(1)));
+    assert_eq!(
+        computer.run(),
+        Err(IntcodeError::InvalidParamMode {
+            ix: 2,
+            instruction: 304,
+            mode: 3
+        })
+    );
+}
+
+#[test]
+pub fn negative_address() {
+    let mut computer = Computer::new(vec![109, -5, 204, 1, 99]);
+    assert_eq!(
+        computer.run(),
+        Err(IntcodeError::NegativeAddress {
+            ix: 2,
+            instruction: 204,
+            address: -4
+        })
+    );
+}
+
+#[test]
+pub fn jump_out_of_range() {
+    let mut computer = Computer::new(vec![1105, 1, 500, 99]);
+    assert_eq!(
+        computer.run(),
+        Err(IntcodeError::JumpOutOfRange {
+            ix: 0,
+            instruction: 1105,
+            target: 500
+        })
+    );
+}
+
+#[test]
+pub fn errors_are_repeatable() {
+    let mut computer = Computer::new(vec![1105, 1, 500, 99]);
+    let err = computer.run().unwrap_err();
+    assert_eq!(computer.ix(), 0);
+    assert_eq!(computer.run(), Err(err));
+
+    let mut computer = Computer::new(vec![1101, i64::MAX, 1, 7, 99, 0, 0, 0]);
+    computer.set_arithmetic(Arithmetic::Checked);
+    let err = computer.run().unwrap_err();
+    assert_eq!(computer.run(), Err(err));
+    assert_eq!((computer.ix(), computer.cycles()), (0, 0));
+}
+
+#[test]
+pub fn relative_address_overflow() {
+    for &arithmetic in &[Arithmetic::Native, Arithmetic::Checked] {
+        let mut computer = Computer::new(vec![109, i64::MAX, 204, 1, 99]);
+        computer.set_arithmetic(arithmetic);
+        assert_eq!(
+            computer.run(),
+            Err(IntcodeError::Overflow {
+                ix: 2,
+                instruction: 204
+            })
+        );
+        assert_eq!(computer.ix(), 2);
+    }
+}
+
+#[test]
+pub fn relative_base_overflow() {
+    for &arithmetic in &[Arithmetic::Native, Arithmetic::Checked, Arithmetic::Big] {
+        for &traced in &[false, true] {
+            let mut computer = Computer::new(vec![109, i64::MAX, 109, 1, 99]);
+            computer.set_arithmetic(arithmetic);
+            if traced {
+                computer.set_tracer(Vec::new());
+            }
+            assert_eq!(
+                computer.run(),
+                Err(IntcodeError::Overflow {
+                    ix: 2,
+                    instruction: 109
+                })
+            );
+            assert_eq!(computer.relative_base(), i64::MAX);
+        }
+    }
+}
+
+#[test]
+pub fn halt_is_repeatable() {
+    let mut computer = Computer::new(vec![99]);
+    assert_eq!(computer.run(), Ok(State::Complete));
+    assert_eq!(computer.run(), Ok(State::Complete));
+}
+
+#[test]
+pub fn watchpoint_pauses_after_write() {
+    let mut computer = Computer::new(vec![1101, 2, 3, 7, 4, 7, 99, 0]);
+    let id = computer.watch(7..8, Access::Write);
+    let hit = Hit {
+        id,
+        ix: 0,
+        address: 7,
+        access: Access::Write,
+        value: 5,
+    };
+    assert_eq!(computer.run(), Ok(State::Watchpoint(hit)));
+    assert_eq!(computer.ix(), 4);
+    assert_eq!(computer.run(), Ok(State::Output(5)));
+    assert_eq!(computer.run(), Ok(State::Complete));
+}
+
+#[test]
+pub fn watchpoint_callbacks_see_traffic() {
+    use crate::snapshot::Snapshot;
+    use std::sync::{Arc, Mutex};
+
+    // [9] = [9] + [10]; [0] = [9] * [9]
+    let mut computer = Computer::new(vec![1, 9, 10, 9, 2, 9, 9, 0, 99, 3, 4]);
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let log = seen.clone();
+    computer.watch_with(9..11, Access::ReadWrite, move |hit| {
+        log.lock().unwrap().push((hit.ix, hit.access, hit.value))
+    });
+    let feeds = computer.watch(0..1, Access::Write);
+    match computer.run() {
+        Ok(State::Watchpoint(hit)) => assert_eq!((hit.id, hit.ix, hit.value), (feeds, 4, 49)),
+        other => panic!("expected watchpoint, got {:?}", other),
+    }
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            (0, Access::Read, 3),
+            (0, Access::Read, 4),
+            (0, Access::Write, 7),
+            (4, Access::Read, 7),
+            (4, Access::Read, 7),
+        ]
+    );
+    assert!(computer.unwatch(feeds));
+    assert_eq!(computer.run(), Ok(State::Complete));
+}
+
+#[test]
+pub fn step_limit_stops_infinite_loop() {
+    let mut computer = Computer::new(vec![1105, 1, 0]);
+    computer.set_step_limit(Some(1000));
+    assert_eq!(computer.run(), Ok(State::BudgetExhausted));
+    assert_eq!(computer.cycles(), 1000);
+    computer.set_step_limit(Some(1500));
+    assert_eq!(computer.run(), Ok(State::BudgetExhausted));
+    assert_eq!(computer.cycles(), 1500);
+}
+
+#[test]
+pub fn counts_per_opcode() {
+    let mut computer = Computer::new(vec![3, 9, 1002, 9, 2, 9, 4, 9, 99, 0]);
+    assert_eq!(computer.run(), Ok(State::AwaitingInput));
+    assert_eq!(computer.cycles(), 0);
+    computer.add_input(4);
+    assert_eq!(computer.run(), Ok(State::Output(8)));
+    assert_eq!(computer.run(), Ok(State::Complete));
+    assert_eq!(computer.cycles(), 4);
+    let counts: Vec<(i64, u64)> = computer.opcode_counts().into_iter().collect();
+    assert_eq!(counts, [(2, 1), (3, 1), (4, 1), (99, 1)]);
+}
+
+#[test]
+pub fn checked_arithmetic_overflow() {
+    let program = vec![1101, i64::MAX, 1, 7, 4, 7, 99, 0];
+    let mut computer = Computer::new(program.clone());
+    computer.set_arithmetic(Arithmetic::Checked);
+    let err = computer.run().unwrap_err();
+    assert_eq!(
+        err,
+        IntcodeError::Overflow {
+            ix: 0,
+            instruction: 1101
+        }
+    );
+    assert_eq!(err.to_string(), "arithmetic overflow in 1101 at 0");
+
+    let mut computer = Computer::new(program);
+    computer.set_arithmetic(Arithmetic::Big);
+    assert_eq!(
+        computer.run(),
+        Ok(State::BigOutput(&BigInt::from(i64::MAX) + &BigInt::from(1)))
+    );
+}
+
+#[test]
+pub fn big_arithmetic_powers_of_two() {
+    let program = crate::assembler::assemble(
+        "
+    loop:   MUL [x], #2, [x]
+            ADD [n], #-1, [n]
+            JT [n], #loop
+            OUT [x]
+            LT [x], #0, [t]
+            OUT [t]
+            MUL [x], #0, [x]
+            OUT [x]
+            HLT
+    x:      .data 1
+    n:      .data 100
+    t:      .data 0
+    ",
+    )
+    .unwrap();
+    let mut computer = Computer::new(program);
+    computer.set_arithmetic(Arithmetic::Big);
+    match computer.run() {
+        Ok(State::BigOutput(n)) => assert_eq!(n.to_string(), "1267650600228229401496703205376"),
+        other => panic!("expected a big output, got {:?}", other),
+    }
+    assert_eq!(computer.run(), Ok(State::Output(0)));
+    assert_eq!(computer.run(), Ok(State::Output(0)));
+    assert_eq!(computer.run(), Ok(State::Complete));
+}
+
+#[test]
+pub fn cached_engine_sees_self_modification() {
+    // Outputs its own immediate operand, bumping it each time round.
+    let program = crate::assembler::assemble(
+        "
+    start:  OUT #1
+            ADD [start+1], #1, [start+1]
+            LT [start+1], #4, [t]
+            JT [t], #start
+            HLT
+    t:      .data 0
+    ",
+    )
+    .unwrap();
+    for &engine in &[Engine::Interpreter, Engine::Cached] {
+        let mut computer = Computer::new(program.clone());
+        computer.set_engine(engine);
+        let mut outputs = Vec::new();
+        while let State::Output(value) = computer.run().unwrap() {
+            outputs.push(value);
+        }
+        assert_eq!(outputs, [1, 2, 3], "{:?}", engine);
+    }
+}
+
+#[test]
+pub fn fused_compare_and_branch() {
+    // The counting loop with its LT as LTJ, run fast and, with an undo log
+    // attached, a step at a time.
+    let mut program = crate::samples::COUNTER.to_vec();
+    program[6] = 1021;
+    // LTJ #1, #2, [9] then JT [9], #100, which is out of range.
+    let faulty = vec![1121, 1, 2, 9, 1005, 9, 100, 99, 0, 0];
+    for &engine in &[Engine::Interpreter, Engine::Cached] {
+        for &history in &[None, Some(10)] {
+            let mut computer = Computer::new(program.clone());
+            computer.set_dialect(Dialect::fused());
+            computer.set_engine(engine);
+            computer.set_history(history);
+            let mut outputs = Vec::new();
+            while let State::Output(value) = computer.run().unwrap() {
+                outputs.push(value);
+            }
+            assert_eq!(outputs, [1, 2, 3]);
+            assert_eq!(computer.cycles(), 10, "{:?} {:?}", engine, history);
+
+            let mut computer = Computer::new(faulty.clone());
+            computer.set_dialect(Dialect::fused());
+            computer.set_engine(engine);
+            computer.set_history(history);
+            assert_eq!(
+                computer.run(),
+                Err(IntcodeError::JumpOutOfRange {
+                    ix: 4,
+                    instruction: 1005,
+                    target: 100
+                })
+            );
+            assert_eq!(computer.ix(), 4);
+            assert_eq!(computer.peek(9), 1);
+        }
+    }
+}
+
+#[test]
+pub fn cached_engine_survives_restore() {
+    let mut computer = Computer::new(vec![1, 0, 0, 0, 99, 10, 20, 30]);
+    computer.set_engine(Engine::Cached);
+    let program = computer.snapshot();
+    for &(noun, verb, sum) in &[(5, 6, 30), (6, 7, 50), (5, 5, 20)] {
+        computer.restore(&program);
+        computer.poke(1, noun);
+        computer.poke(2, verb);
+        assert_eq!(computer.run(), Ok(State::Complete));
+        assert_eq!(computer.peek(0), sum);
+    }
+}
+
+#[test]
+pub fn step_back_to_the_start() {
+    // Stores 5 and 7 one past the relative base, multiplies them and outputs
+    // the product twice.
+    let program = vec![
+        109, 20, 21101, 5, 0, 1, 21101, 7, 0, 2, 22202, 1, 2, 1, 204, 1, 204, 1, 99,
+    ];
+    let mut computer = Computer::new(program.clone());
+    computer.set_engine(Engine::Cached);
+    computer.set_history(Some(100));
+    assert_eq!(computer.run(), Ok(State::Output(35)));
+    assert_eq!(computer.undoable(), 5);
+    assert!(computer.rewind_to_output());
+    assert_eq!(computer.ix(), 14);
+    assert_eq!(computer.run(), Ok(State::Output(35)));
+    assert_eq!(computer.run(), Ok(State::Output(35)));
+    assert_eq!(computer.run(), Ok(State::Complete));
+    assert_eq!(computer.step_back(100), 7);
+    assert_eq!(computer.cycles(), 0);
+    assert_eq!(computer.relative_base(), 0);
+    assert_eq!(computer.memory.to_vec()[..program.len()], program[..]);
+    assert!(!computer.rewind_to_output());
+}
+
+#[test]
+pub fn rewind_to_input() {
+    // Doubles each input.
+    let program = vec![3, 9, 102, 2, 9, 9, 4, 9, 1105, 1, 0];
+    let mut computer = Computer::new(program);
+    computer.set_history(Some(3));
+    computer.add_input(5);
+    assert_eq!(computer.run(), Ok(State::Output(10)));
+    assert_eq!(computer.rewind_to_input(), Some(5));
+    assert!(computer.inputs().is_empty());
+    computer.add_input(7);
+    assert_eq!(computer.run(), Ok(State::Output(14)));
+    assert_eq!(computer.run(), Ok(State::AwaitingInput));
+    // The log only holds three instructions, and the last input was four ago.
+    assert_eq!(computer.rewind_to_input(), None);
+    assert_eq!(computer.step_back(5), 3);
+}
+
+// A benchmark rather than a test: `cargo test --release -- --ignored --nocapture copy_loop_speed`.
+// Three million instructions of copying a word and counting; the cached
+// engine should come out ahead of the interpreter.
+#[test]
+#[ignore]
+pub fn copy_loop_speed() {
+    // [51] = [50]; [52] += 1; [54] = [52] < [53]; if [54] goto 0; HLT
+    let mut program = vec![
+        1001, 50, 0, 51, 1001, 52, 1, 52, 7, 52, 53, 54, 1005, 54, 0, 99,
+    ];
+    program.resize(55, 0);
+    program[50] = 7;
+    program[53] = 750_000;
+    let time = |engine: Engine| {
+        (0..5)
+            .map(|_| {
+                let mut computer = Computer::new(program.clone());
+                computer.set_engine(engine);
+                let start = std::time::Instant::now();
+                assert_eq!(computer.run(), Ok(State::Complete));
+                assert_eq!(computer.cycles(), 3_000_001);
+                start.elapsed()
+            })
+            .min()
+            .unwrap()
+    };
+    let interpreter = time(Engine::Interpreter);
+    let cached = time(Engine::Cached);
+    println!("interpreter {:?}, cached {:?}", interpreter, cached);
+    assert!(cached < interpreter);
+}
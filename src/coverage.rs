@@ -0,0 +1,201 @@
+use crate::computer::{opcode_info, ParamKind, ParamMode};
+use crate::disassembler::{disassemble, Decoded};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+// What a run, or several merged runs, touched: instructions executed (and in
+// which mode combinations), words read and written as data, and which way
+// each conditional jump went.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    executed: BTreeMap<usize, u64>,
+    forms: BTreeSet<i64>,
+    reads: BTreeSet<usize>,
+    writes: BTreeSet<usize>,
+    branches: BTreeMap<usize, Branch>,
+}
+
+// An instruction word without any mode digits past its last parameter, so
+// `1101` and `01101` count as the same form.
+fn form(instruction: i64) -> i64 {
+    match opcode_info(instruction % 100) {
+        Some(info) => instruction % (100 * 10i64.pow(info.params.len() as u32)),
+        None => instruction,
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn execute(&mut self, ix: usize, instruction: i64, taken: Option<bool>) {
+        *self.executed.entry(ix).or_insert(0) += 1;
+        self.forms.insert(form(instruction));
+        if let Some(taken) = taken {
+            let branch = self.branches.entry(ix).or_default();
+            match taken {
+                true => branch.taken += 1,
+                false => branch.not_taken += 1,
+            }
+        }
+    }
+
+    pub(crate) fn read(&mut self, address: usize) {
+        self.reads.insert(address);
+    }
+
+    pub(crate) fn write(&mut self, address: usize) {
+        self.writes.insert(address);
+    }
+
+    pub fn merge(&mut self, other: &Coverage) {
+        for (&ix, &count) in &other.executed {
+            *self.executed.entry(ix).or_insert(0) += count;
+        }
+        self.forms.extend(&other.forms);
+        self.reads.extend(&other.reads);
+        self.writes.extend(&other.writes);
+        for (&ix, branch) in &other.branches {
+            let ours = self.branches.entry(ix).or_default();
+            ours.taken += branch.taken;
+            ours.not_taken += branch.not_taken;
+        }
+    }
+
+    pub fn executions(&self, address: usize) -> u64 {
+        self.executed.get(&address).cloned().unwrap_or(0)
+    }
+
+    pub fn was_read(&self, address: usize) -> bool {
+        self.reads.contains(&address)
+    }
+
+    pub fn was_written(&self, address: usize) -> bool {
+        self.writes.contains(&address)
+    }
+
+    pub fn branch(&self, address: usize) -> Option<Branch> {
+        self.branches.get(&address).cloned()
+    }
+
+    // Instruction words executed, e.g. 1002 for MUL with an immediate second operand.
+    pub fn forms(&self) -> &BTreeSet<i64> {
+        &self.forms
+    }
+
+    // Every form of the given opcodes using the given modes that never ran.
+    // Write parameters are never immediate, so those forms aren't counted.
+    pub fn untested_forms(&self, codes: &[i64], modes: &[ParamMode]) -> Vec<i64> {
+        let mut untested = Vec::new();
+        for info in codes.iter().filter_map(|&code| opcode_info(code)) {
+            let mut forms = vec![info.code];
+            for (i, kind) in info.params.iter().enumerate() {
+                let place = 10i64.pow(i as u32 + 2);
+                forms = forms
+                    .iter()
+                    .flat_map(|&form| {
+                        modes
+                            .iter()
+                            .filter(|&&mode| {
+                                !(*kind == ParamKind::Write && mode == ParamMode::Immediate)
+                            })
+                            .map(move |mode| form + mode.digit() * place)
+                    })
+                    .collect();
+            }
+            forms.sort_unstable();
+            untested.extend(forms.into_iter().filter(|form| !self.forms.contains(form)));
+        }
+        untested
+    }
+
+    // A disassembly of `program` with executed instructions starred and each
+    // line annotated with data accesses and branch directions.
+    pub fn annotate(&self, program: &[i64]) -> String {
+        let executed: HashSet<usize> = self.executed.keys().cloned().collect();
+        let listing = disassemble(program, Some(&executed));
+        let (mut instructions, mut directions, mut taken) = (0, 0, 0);
+        let mut body = String::new();
+        for line in &listing.lines {
+            let mut notes = Vec::new();
+            if let Decoded::Instruction { mnemonic, .. } = &line.decoded {
+                instructions += 1;
+                if *mnemonic == "JT" || *mnemonic == "JF" {
+                    let branch = self.branch(line.address).unwrap_or_default();
+                    directions += 2;
+                    taken += (branch.taken > 0) as usize + (branch.not_taken > 0) as usize;
+                    notes.push(format!(
+                        "taken {}, not taken {}",
+                        branch.taken, branch.not_taken
+                    ));
+                }
+            }
+            let span = line.address..line.address + line.raw.len();
+            if span.clone().any(|address| self.was_read(address)) {
+                notes.push("read".to_string());
+            }
+            if span.clone().any(|address| self.was_written(address)) {
+                notes.push("written".to_string());
+            }
+            write!(body, "{}", line).unwrap();
+            if !notes.is_empty() {
+                write!(body, "  | {}", notes.join(", ")).unwrap();
+            }
+            body.push('\n');
+        }
+        let covered = listing.lines.iter().filter(|line| line.executed).count();
+        format!(
+            "{} of {} instructions executed, {} of {} branch directions taken, {} words read, {} written\n{}",
+            covered,
+            instructions,
+            taken,
+            directions,
+            self.reads.len(),
+            self.writes.len(),
+            body
+        )
+    }
+}
+
+#[test]
+pub fn branches_and_data() {
+    use crate::computer::{Computer, State};
+
+    // Outputs 0 for a zero input and 1 otherwise, by jumping.
+    let program = vec![3, 3, 1105, -1, 9, 1101, 0, 0, 12, 4, 12, 99, 1];
+    let mut coverage = Coverage::new();
+    for &input in &[0, 5] {
+        let mut computer = Computer::new(program.clone());
+        computer.set_coverage(true);
+        computer.add_input(input);
+        while computer.run() != Ok(State::Complete) {}
+        coverage.merge(computer.coverage().unwrap());
+    }
+    assert_eq!(coverage.executions(0), 2);
+    assert_eq!(
+        coverage.branch(2),
+        Some(Branch {
+            taken: 1,
+            not_taken: 1
+        })
+    );
+    assert!(coverage.was_written(3) && coverage.was_read(12) && coverage.was_written(12));
+    assert_eq!(coverage.forms().len(), 5);
+    assert_eq!(
+        coverage.untested_forms(&[1, 5], &[ParamMode::Position, ParamMode::Immediate]),
+        [1, 101, 1001, 5, 105, 1005]
+    );
+    let text = coverage.annotate(&program);
+    assert!(text.starts_with(
+        "5 of 5 instructions executed, 2 of 2 branch directions taken, 1 words read, 2 written\n"
+    ));
+    assert!(text.contains("; 1105,-1,9  | taken 1, not taken 1, written\n"));
+    assert!(text.contains("; 1  | read, written\n"));
+}
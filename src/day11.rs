@@ -103,7 +103,7 @@ impl Robot {
         println!("loop start");
 
         loop {
-            let result = computer.run();
+            let result = computer.run().unwrap();
             // println!("{:?}", self);
             // println!("{:?}", self.current_state(panels));
             // println!("{:?}", result);
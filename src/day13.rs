@@ -67,10 +67,11 @@ pub fn part1(input: &[i64]) -> usize {
     let mut tiles: Vec<Tile> = Vec::new();
 
     loop {
-        match computer.run() {
+        match computer.run().unwrap() {
             State::Idle => return 0,
             State::AwaitingInput => return 0,
             State::Output(value) => outputs.push(value),
+            State::Watchpoint(_) | State::BudgetExhausted | State::BigOutput(_) => {}
             State::Complete => {
                 return tiles
                     .iter()
@@ -103,7 +104,7 @@ pub fn part2(input: &[i64]) -> i64 {
     let mut score: i64 = 0;
 
     loop {
-        match computer.run() {
+        match computer.run().unwrap() {
             State::Idle => return 0,
             State::AwaitingInput => {
                 let mut ball_x = 0;
@@ -118,6 +119,7 @@ pub fn part2(input: &[i64]) -> i64 {
                 computer.add_input(ball_x.cmp(&paddle_x) as i64);
             }
             State::Output(value) => outputs.push(value),
+            State::Watchpoint(_) | State::BudgetExhausted | State::BigOutput(_) => {}
             State::Complete => return score,
         }
 
@@ -1,9 +1,13 @@
 use crate::computer::Computer;
+use crate::computer::{Engine, State};
+use crate::search::{patch_grid, Candidate, Search};
+use crate::snapshot::Snapshot;
+use crate::symbolic::{self, Value};
 use std::str::FromStr;
 
 fn run_test(before: &[i64], after: &[i64]) {
     let mut computer = Computer::new(before.to_owned());
-    computer.run();
+    computer.run().unwrap();
     assert!(
         computer.memory == after,
         "memory didn't reach expected final state"
@@ -55,36 +59,82 @@ pub fn part1(input: &[i64]) -> i64 {
     program[1] = 12;
     program[2] = 2;
     let mut computer = Computer::new(program);
-    computer.run();
-    (computer.memory)[0]
+    computer.run().unwrap();
+    computer.memory.get(0)
 }
 
-#[aoc(day2, part2)]
-pub fn part2(input: &[i64]) -> usize {
-    let mut count: usize = 0;
-    let mut result: i64;
-
-    loop {
-        for noun in 0..count {
-            let mut program = input.to_vec();
-            program[1] = noun as i64;
-            program[2] = count as i64;
-            let mut computer = Computer::new(program);
-            computer.run();
-            if (computer.memory)[0] == 19690720 {
-                return 100 * noun + count;
-            }
-        }
-        for verb in 0..count {
-            let mut program = input.to_vec();
-            program[1] = count as i64;
-            program[2] = verb as i64;
-            let mut computer = Computer::new(program);
-            computer.run();
-            if (computer.memory)[0] == 19690720 {
-                return 100 * count + verb;
-            }
+// Day 2 programs are straight-line, so anything still running by now is stuck.
+const STEP_LIMIT: u64 = 10_000;
+
+// Restarting one machine from a snapshot keeps its decoded instructions, so
+// only the patched first instruction is decoded again on each attempt.
+fn output_for(computer: &mut Computer, program: &Snapshot, candidate: &Candidate) -> Option<i64> {
+    computer.restore(program);
+    candidate.apply(computer);
+    computer.set_step_limit(Some(computer.cycles() + STEP_LIMIT));
+    match computer.run() {
+        Ok(State::Complete) => Some(computer.memory.get(0)),
+        _ => None,
+    }
+}
+
+const TARGET: i64 = 19690720;
+
+// Day 2 programs are straight-line add/mul code, so whatever ends up in [0] is
+// a polynomial in the noun and verb. The puzzle input gives a linear one (of
+// the form `a * noun + verb + b`), which can be solved outright.
+fn solve_symbolically(input: &[i64]) -> Option<usize> {
+    let memory = symbolic::execute(input, &[1, 2]).ok()?;
+    match &memory[0] {
+        Value::Known(expr) => {
+            let values = symbolic::solve(expr, TARGET, 0..100)?;
+            Some((100 * values[0] + values[1]) as usize)
         }
-        count += 1;
+        Value::Unknown => None,
     }
 }
+
+fn brute_force(input: &[i64]) -> usize {
+    let values: Vec<i64> = (0..100).collect();
+    let mut search = Search::new(patch_grid(&[1, 2], &values));
+    search.set_target(Some(TARGET));
+    let best = search
+        .run_with(
+            || {
+                let mut computer = Computer::new(input.to_vec());
+                computer.set_engine(Engine::Cached);
+                let program = computer.snapshot();
+                (computer, program)
+            },
+            |(computer, program), candidate| output_for(computer, program, candidate),
+        )
+        .filter(|best| best.score == TARGET)
+        .expect("no noun and verb reach the target");
+    let (noun, verb) = (best.candidate.patches[0].1, best.candidate.patches[1].1);
+    (100 * noun + verb) as usize
+}
+
+#[aoc(day2, part2)]
+pub fn part2(input: &[i64]) -> usize {
+    solve_symbolically(input).unwrap_or_else(|| brute_force(input))
+}
+
+#[test]
+pub fn symbolic_matches_brute_force() {
+    // [0] = 1000 * noun + verb + 19678686
+    let program = [
+        1, 0, 0, 3, 1002, 1, 1000, 0, 1, 0, 2, 0, 1001, 0, 19678686, 0, 99,
+    ];
+    assert_eq!(solve_symbolically(&program), Some(1234));
+    assert_eq!(brute_force(&program), 1234);
+}
+
+#[test]
+pub fn data_dependent_jump_falls_back() {
+    // As above, but only if the verb is non-zero.
+    let program = [
+        1, 0, 0, 3, 1005, 2, 9, 99, 0, 1002, 1, 1000, 0, 1, 0, 2, 0, 1001, 0, 19678686, 0, 99,
+    ];
+    assert_eq!(solve_symbolically(&program), None);
+    assert_eq!(part2(&program), 1234);
+}
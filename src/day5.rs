@@ -1,10 +1,11 @@
 use crate::computer::Computer;
 use crate::computer::State;
+use crate::dialect::Dialect;
 use std::str::FromStr;
 
 fn immediate_test(before: &[i64], after: &[i64]) {
     let mut computer = Computer::new(before.to_owned());
-    computer.run();
+    computer.run().unwrap();
     assert!(
         computer.memory == after,
         "memory didn't reach expected final state"
@@ -14,7 +15,7 @@ fn immediate_test(before: &[i64], after: &[i64]) {
 fn run_test(before: &[i64], input: i64, output: i64) {
     let mut computer = Computer::new(before.to_owned());
     computer.add_input(input);
-    let result = computer.run();
+    let result = computer.run().unwrap();
     println!("{:?}", computer);
     match result {
         State::Output(out) => assert!(out == output),
@@ -81,6 +82,51 @@ pub fn test8() {
     run_test(program, 9, 1001);
 }
 
+// The example programs above, with the inputs each is run on.
+const EXAMPLES: &[(&[i64], &[i64])] = &[
+    (&[3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8], &[8, 7]),
+    (&[3, 9, 7, 9, 10, 9, 4, 9, 99, -1, 8], &[8, 7]),
+    (&[3, 3, 1108, -1, 8, 3, 4, 3, 99], &[8, 7]),
+    (&[3, 3, 1107, -1, 8, 3, 4, 3, 99], &[8, 7]),
+    (
+        &[3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9],
+        &[0, 7],
+    ),
+    (&[3, 3, 1105, -1, 9, 1101, 0, 0, 12, 4, 12, 99, 1], &[0, 7]),
+    (
+        &[
+            3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0,
+            0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4,
+            20, 1105, 1, 46, 98, 99,
+        ],
+        &[7, 8, 9],
+    ),
+];
+
+#[test]
+pub fn examples_coverage() {
+    use crate::computer::ParamMode;
+    use crate::coverage::Coverage;
+
+    let mut coverage = Coverage::new();
+    for (program, inputs) in EXAMPLES {
+        for &input in inputs.iter() {
+            let mut computer = Computer::new(program.to_vec());
+            computer.set_coverage(true);
+            computer.add_input(input);
+            while computer.run() != Ok(State::Complete) {}
+            coverage.merge(computer.coverage().unwrap());
+        }
+    }
+    // Every day 5 opcode runs, but not in every mode: MUL only ever runs as
+    // 1002, for instance, so the other forms rely on the real input.
+    let modes = [ParamMode::Position, ParamMode::Immediate];
+    assert_eq!(
+        coverage.untested_forms(&[1, 2, 3, 4, 5, 6, 7, 8, 99], &modes),
+        [101, 1001, 2, 102, 1102, 5, 105, 106, 1007, 108]
+    );
+}
+
 #[aoc_generator(day5)]
 pub fn input_generator(input: &str) -> Vec<i64> {
     input
@@ -92,8 +138,9 @@ pub fn input_generator(input: &str) -> Vec<i64> {
 #[aoc(day5, part1)]
 pub fn part1(input: &[i64]) -> i64 {
     let mut computer = Computer::new(input.to_owned());
+    computer.set_dialect(Dialect::day5());
     computer.add_input(1);
-    match computer.run() {
+    match computer.run().unwrap() {
         State::Output(output) => output,
         _ => 0,
     }
@@ -102,8 +149,9 @@ pub fn part1(input: &[i64]) -> i64 {
 #[aoc(day5, part2)]
 pub fn part2(input: &[i64]) -> i64 {
     let mut computer = Computer::new(input.to_owned());
+    computer.set_dialect(Dialect::day5());
     computer.add_input(5);
-    match computer.run() {
+    match computer.run().unwrap() {
         State::Output(output) => output,
         _ => 0,
     }
@@ -1,5 +1,7 @@
 use crate::computer::Computer;
 use crate::computer::State;
+use crate::network::{Network, Outcome};
+use crate::search::Search;
 use std::str::FromStr;
 
 fn get_permutations(v: Vec<i64>) -> Vec<Vec<i64>> {
@@ -19,79 +21,54 @@ fn get_permutations(v: Vec<i64>) -> Vec<Vec<i64>> {
     }
 }
 
+fn amp(program: &[i64]) -> Computer {
+    Computer::new(program.to_owned())
+}
+
 fn run_amps_once(program: &[i64], phases: Vec<i64>) -> i64 {
-    let mut n: i64 = 0;
-    for i in phases {
-        let mut computer = Computer::new(program.to_owned());
-        computer.add_input(i);
-        computer.add_input(n);
-        match computer.run() {
-            State::Output(output) => n = output,
-            _ => {}
-        }
-    }
-    n
+    let mut network = Network::new();
+    let amps: Vec<usize> = phases
+        .iter()
+        .map(|&phase| network.add_with_inputs(amp(program), &[phase]))
+        .collect();
+    network.chain(&amps);
+    network.feed(amps[0], 0);
+    let report = network.run().unwrap();
+    report.sink_outputs()[0].1.last().cloned().unwrap_or(0)
 }
 
 fn max_signal_single_run(program: &[i64]) -> i64 {
     let phases = [0, 1, 2, 3, 4].to_vec();
     let permutations = get_permutations(phases);
-
-    let mut max_value = 0;
-    for perm in permutations {
-        let value = run_amps_once(program, perm);
-        if value > max_value {
-            max_value = value;
-        }
-    }
-    max_value
+    Search::new(permutations)
+        .run(|perm| Some(run_amps_once(program, perm.to_owned())))
+        .map_or(0, |best| best.score)
 }
 
 fn run_amps_feedback_loop(program: &[i64], phases: Vec<i64>) -> i64 {
-    let amp = || Computer::new(program.to_owned());
-    let mut amps = Vec::new();
-
-    for i in 0..5 {
-        amps.push(Computer::new(program.to_owned()));
-        amps[i].add_input(phases[i]);
-        amps[i].run();
-    }
-
-    let mut i: usize = 0;
-    let mut v: i64 = 0;
-    let mut complete = false;
-
-    loop {
-        for i in 0..5 {
-            // println!("Amp: {}: {}", i, v);
-            amps[i].add_input(v);
-            match amps[i].run() {
-                State::Output(output) => v = output,
-                State::Complete => complete = true,
-                _ => {}
-            }
-        }
-
-        // println!("Loop result: {}", v);
-
-        if complete {
-            return v;
-        }
+    let mut network = Network::new();
+    let amps: Vec<usize> = phases
+        .iter()
+        .map(|&phase| network.add_with_inputs(amp(program), &[phase]))
+        .collect();
+    network.ring(&amps);
+    network.feed(amps[0], 0);
+    let report = network.run().unwrap();
+    if let Outcome::Deadlock(stuck) | Outcome::Livelock(stuck) = &report.outcome {
+        panic!("feedback loop stuck:\n{}", stuck);
     }
+    report.outputs[amps[amps.len() - 1]]
+        .last()
+        .cloned()
+        .unwrap_or(0)
 }
 
 fn max_signal_feedback_loop(program: &[i64]) -> i64 {
     let phases = [5, 6, 7, 8, 9].to_vec();
     let permutations = get_permutations(phases);
-
-    let mut max_value = 0;
-    for perm in permutations {
-        let value = run_amps_feedback_loop(program, perm.to_owned());
-        if value > max_value {
-            max_value = value;
-        }
-    }
-    max_value
+    Search::new(permutations)
+        .run(|perm| Some(run_amps_feedback_loop(program, perm.to_owned())))
+        .map_or(0, |best| best.score)
 }
 
 #[test]
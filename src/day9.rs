@@ -11,7 +11,7 @@ pub fn test1() {
     let mut computer = Computer::new(program.to_owned());
     let mut i = 0;
     loop {
-        let state = computer.run();
+        let state = computer.run().unwrap();
         match state {
             State::Complete => break,
             State::Output(output) => assert!(output == program[i]),
@@ -24,7 +24,7 @@ pub fn test1() {
 fn run_test(before: &[i64], output: i64) {
     let mut computer = Computer::new(before.to_owned());
     computer.add_input(123);
-    let result = computer.run();
+    let result = computer.run().unwrap();
     match result {
         State::Output(out) => assert!(out == output),
         _ => assert!(false, "No output!"),
@@ -111,7 +111,7 @@ pub fn part1(input: &[i64]) -> i64 {
     let mut output: i64 = 1;
     computer.add_input(output);
     loop {
-        let state = computer.run();
+        let state = computer.run().unwrap();
         match state {
             State::Output(out) => {
                 output = out;
@@ -130,7 +130,7 @@ pub fn part2(input: &[i64]) -> i64 {
     let mut output: i64 = 2;
     computer.add_input(output);
     loop {
-        let state = computer.run();
+        let state = computer.run().unwrap();
         match state {
             State::Output(out) => {
                 output = out;
@@ -0,0 +1,368 @@
+use crate::calls::{Call, CallTracker};
+use crate::computer::{Access, Computer, IntcodeError, State};
+use crate::disassembler::{decode_at, Decoded, Line};
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+// The most words `x` prints or `m` watches at once.
+const MAX_COUNT: i64 = 1024;
+
+// The words `x <addr> [n]` and `m <addr> [n]` cover.
+fn word_range(args: &[i64]) -> Result<Range<usize>, String> {
+    let address = args[0];
+    let count = args.get(1).cloned().unwrap_or(1);
+    if address < 0 {
+        return Err(format!("negative address {}", address));
+    }
+    if !(1..=MAX_COUNT).contains(&count) {
+        return Err(format!("count must be from 1 to {}", MAX_COUNT));
+    }
+    let end = address
+        .checked_add(count)
+        .ok_or_else(|| format!("{} words from {} is past the end of memory", count, address))?;
+    Ok(address as usize..end as usize)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Stepped,
+    Breakpoint(usize),
+    Yielded(State),
+}
+
+#[derive(Debug)]
+pub struct Debugger {
+    pub computer: Computer,
+    breakpoints: BTreeSet<usize>,
+    calls: Option<Arc<Mutex<CallTracker>>>,
+}
+
+impl Debugger {
+    pub fn new(computer: Computer) -> Self {
+        Self {
+            computer,
+            breakpoints: BTreeSet::new(),
+            calls: None,
+        }
+    }
+
+    // Installs a call tracker as the machine's tracer, replacing any other, so
+    // that `backtrace` can say which subroutines are active.
+    pub fn track_calls(&mut self) {
+        let tracker = Arc::new(Mutex::new(CallTracker::new()));
+        self.computer.set_tracer(tracker.clone());
+        self.calls = Some(tracker);
+    }
+
+    // Open calls, innermost first, or None if calls aren't being tracked.
+    pub fn backtrace(&self) -> Option<Vec<Call>> {
+        let tracker = self.calls.as_ref()?.lock().unwrap();
+        Some(tracker.backtrace().cloned().collect())
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &usize> {
+        self.breakpoints.iter()
+    }
+
+    pub fn step(&mut self) -> Result<Event, IntcodeError> {
+        Ok(match self.computer.step()? {
+            Some(state) => Event::Yielded(state),
+            None => Event::Stepped,
+        })
+    }
+
+    // Runs until a breakpoint or until the machine yields. The instruction under
+    // a breakpoint we're already stopped at is always executed first.
+    pub fn resume(&mut self) -> Result<Event, IntcodeError> {
+        loop {
+            if let Event::Yielded(state) = self.step()? {
+                return Ok(Event::Yielded(state));
+            }
+            if self.breakpoints.contains(&self.computer.ix()) {
+                return Ok(Event::Breakpoint(self.computer.ix()));
+            }
+        }
+    }
+
+    // Like `resume`, but outputs are collected rather than stopping the machine.
+    pub fn step_over_output(&mut self) -> Result<(Event, Vec<i64>), IntcodeError> {
+        let mut outputs = Vec::new();
+        loop {
+            match self.resume()? {
+                Event::Yielded(State::Output(value)) => {
+                    outputs.push(value);
+                    if self.breakpoints.contains(&self.computer.ix()) {
+                        return Ok((Event::Breakpoint(self.computer.ix()), outputs));
+                    }
+                }
+                event => return Ok((event, outputs)),
+            }
+        }
+    }
+
+    pub fn current_line(&self) -> Line {
+        let ix = self.computer.ix();
+        // No instruction is longer than four words.
+        let end = (ix + 4).min(self.computer.memory.len());
+        let window: Vec<i64> = (ix..end).map(|a| self.computer.peek(a)).collect();
+        match decode_at(&window, 0) {
+            Some((info, operands)) => Line {
+                address: ix,
+                decoded: Decoded::Instruction {
+                    mnemonic: info.mnemonic,
+                    operands,
+                },
+                raw: window[..=info.params.len()].to_vec(),
+                executed: false,
+            },
+            None => Line {
+                address: ix,
+                decoded: Decoded::Data(self.computer.peek(ix)),
+                raw: vec![self.computer.peek(ix)],
+                executed: false,
+            },
+        }
+    }
+
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        writeln!(output, "{}", self.current_line())?;
+        for line in input.lines() {
+            let line = line?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let args: Vec<i64> = words
+                .iter()
+                .skip(1)
+                .filter_map(|w| w.parse().ok())
+                .collect();
+            if args.len() + 1 < words.len() {
+                writeln!(output, "bad number in '{}'", line)?;
+                continue;
+            }
+            let result = match (words.first().cloned(), args.as_slice()) {
+                (None, _) => continue,
+                (Some("q"), []) => return Ok(()),
+                (Some("s"), []) => self.step(),
+                (Some("c"), []) => self.resume(),
+                (Some("o"), []) => self.step_over_output().map(|(event, outputs)| {
+                    if !outputs.is_empty() {
+                        writeln!(output, "outputs: {:?}", outputs).ok();
+                    }
+                    event
+                }),
+                (Some("b"), [address]) if *address >= 0 => {
+                    self.add_breakpoint(*address as usize);
+                    continue;
+                }
+                (Some("d"), [address]) if *address >= 0 => {
+                    if !self.remove_breakpoint(*address as usize) {
+                        writeln!(output, "no breakpoint at {}", address)?;
+                    }
+                    continue;
+                }
+                (Some("i"), values) if !values.is_empty() => {
+                    for value in values {
+                        self.computer.add_input(*value);
+                    }
+                    continue;
+                }
+                (Some("r"), []) => {
+                    writeln!(
+                        output,
+                        "ix={} rb={} inputs={:?}",
+                        self.computer.ix(),
+                        self.computer.relative_base(),
+                        self.computer.inputs()
+                    )?;
+                    continue;
+                }
+                (Some("bt"), []) => {
+                    match self.backtrace() {
+                        Some(frames) => {
+                            let mut address = self.computer.ix();
+                            for (depth, call) in frames.iter().enumerate() {
+                                writeln!(
+                                    output,
+                                    "#{} {} in {}{:?}",
+                                    depth, address, call.entry, call.arguments
+                                )?;
+                                address = call.site;
+                            }
+                            writeln!(output, "#{} {} in main", frames.len(), address)?;
+                        }
+                        None => writeln!(output, "call tracking is off")?,
+                    }
+                    continue;
+                }
+                (Some("x"), [_]) | (Some("x"), [_, _]) => {
+                    match word_range(&args) {
+                        Ok(range) => {
+                            let start = range.start;
+                            let values: Vec<String> = range
+                                .map(|address| self.computer.peek(address).to_string())
+                                .collect();
+                            writeln!(output, "{}: {}", start, values.join(","))?;
+                        }
+                        Err(message) => writeln!(output, "error: {}", message)?,
+                    }
+                    continue;
+                }
+                (Some("m"), [_]) | (Some("m"), [_, _]) => {
+                    match word_range(&args) {
+                        Ok(range) => {
+                            let id = self.computer.watch(range, Access::ReadWrite);
+                            writeln!(output, "watchpoint {}", id)?;
+                        }
+                        Err(message) => writeln!(output, "error: {}", message)?,
+                    }
+                    continue;
+                }
+                (Some("w"), [address, value]) if *address >= 0 => {
+                    self.computer.poke(*address as usize, *value);
+                    continue;
+                }
+                _ => {
+                    writeln!(
+                        output,
+                        "commands: s, c, o, b <addr>, d <addr>, i <val>.., r, bt, x <addr> [n], w <addr> <val>, m <addr> [n], q"
+                    )?;
+                    continue;
+                }
+            };
+            match result {
+                Ok(Event::Stepped) => {}
+                Ok(Event::Breakpoint(address)) => writeln!(output, "breakpoint at {}", address)?,
+                Ok(Event::Yielded(State::Watchpoint(hit))) => writeln!(
+                    output,
+                    "watchpoint {}: {:?} {} = {} at {}",
+                    hit.id, hit.access, hit.address, hit.value, hit.ix
+                )?,
+                Ok(Event::Yielded(state)) => writeln!(output, "{:?}", state)?,
+                Err(err) => writeln!(output, "error: {}", err)?,
+            }
+            writeln!(output, "{}", self.current_line())?;
+        }
+        Ok(())
+    }
+
+    pub fn repl_stdio(&mut self) -> io::Result<()> {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        self.repl(stdin.lock(), stdout.lock())
+    }
+}
+
+// Debugs `program` from the terminal, with call tracking on so `bt` works.
+pub fn debug_stdio(program: Vec<i64>) -> io::Result<()> {
+    let mut debugger = Debugger::new(Computer::new(program));
+    debugger.track_calls();
+    debugger.repl_stdio()
+}
+
+#[test]
+pub fn breakpoints_and_outputs() {
+    let program = crate::samples::COUNTER.to_vec();
+    let mut debugger = Debugger::new(Computer::new(program));
+    debugger.add_breakpoint(4);
+    assert_eq!(debugger.resume(), Ok(Event::Breakpoint(4)));
+    assert_eq!(debugger.computer.peek(14), 1);
+    assert_eq!(
+        debugger.step_over_output(),
+        Ok((Event::Breakpoint(4), vec![1]))
+    );
+    assert!(debugger.remove_breakpoint(4));
+    debugger.computer.poke(14, 41);
+    assert_eq!(debugger.resume(), Ok(Event::Yielded(State::Output(41))));
+    assert_eq!(
+        debugger.step_over_output(),
+        Ok((Event::Yielded(State::Complete), vec![]))
+    );
+}
+
+#[test]
+pub fn single_step() {
+    let mut debugger = Debugger::new(Computer::new(vec![109, 5, 3, 0, 99]));
+    assert_eq!(debugger.step(), Ok(Event::Stepped));
+    assert_eq!(debugger.computer.relative_base(), 5);
+    assert_eq!(debugger.computer.ix(), 2);
+    assert_eq!(debugger.step(), Ok(Event::Yielded(State::AwaitingInput)));
+    assert_eq!(debugger.computer.ix(), 2);
+    debugger.computer.add_input(7);
+    assert_eq!(debugger.computer.inputs(), &[7]);
+    assert_eq!(debugger.step(), Ok(Event::Stepped));
+    assert_eq!(debugger.computer.peek(0), 7);
+}
+
+#[test]
+pub fn repl_session() {
+    let mut debugger = Debugger::new(Computer::new(vec![3, 7, 4, 7, 99, 0, 0, 0]));
+    let commands = "s\ni 5\nr\nm 7\ns\nx 7\no\nc\nq\n";
+    let mut out = Vec::new();
+    debugger.repl(commands.as_bytes(), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("AwaitingInput"));
+    assert!(out.contains("ix=0 rb=0 inputs=[5]"));
+    assert!(out.contains("watchpoint 0: Write 7 = 5 at 0"));
+    assert!(out.contains("7: 5"));
+    assert!(out.contains("outputs: [5]"));
+    assert!(out.contains("Complete"));
+}
+
+#[test]
+pub fn bad_ranges() {
+    let mut debugger = Debugger::new(Computer::new(vec![99]));
+    let mut out = Vec::new();
+    debugger
+        .repl(
+            "x 9223372036854775807 2\nm 9223372036854775807 2\nx 0 -1\nm 0 0\nx -1\nx 0 100000\nx 0 2\nq\n"
+                .as_bytes(),
+            &mut out,
+        )
+        .unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(
+        out.lines().skip(1).collect::<Vec<_>>(),
+        [
+            "error: 2 words from 9223372036854775807 is past the end of memory",
+            "error: 2 words from 9223372036854775807 is past the end of memory",
+            "error: count must be from 1 to 1024",
+            "error: count must be from 1 to 1024",
+            "error: negative address -1",
+            "error: count must be from 1 to 1024",
+            "0: 99,0",
+        ]
+    );
+}
+
+#[test]
+pub fn backtrace_in_nested_call() {
+    let program = crate::samples::nested_calls();
+    let double = program.len() - 7;
+    let mut debugger = Debugger::new(Computer::new(program));
+    debugger.track_calls();
+    debugger.add_breakpoint(double);
+    let mut out = Vec::new();
+    debugger
+        .repl("c\nbt\nc\nbt\nc\nbt\nq\n".as_bytes(), &mut out)
+        .unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains(&format!(
+        "#0 {} in {}[3]\n#1 26 in 16[3]\n#2 10 in main\n",
+        double, double
+    )));
+    assert!(out.contains(&format!(
+        "#0 {} in {}[6]\n#1 33 in 16[3]\n#2 10 in main\n",
+        double, double
+    )));
+    assert!(out.contains("Output(12)\n"));
+    assert!(out.ends_with("#0 15 in main\n"));
+}
@@ -0,0 +1,386 @@
+use crate::computer::{Computer, OpcodeInfo, ParamKind, ParamMode, State, FUSED_OPCODES, OPCODES};
+use std::collections::BTreeMap;
+use std::fmt;
+
+// The behaviour of an added opcode. Operands arrive resolved the same way as
+// for built-in instructions: read parameters as values, write parameters as
+// addresses (to store through `Computer::write`). Returning a state makes
+// `run` yield it after the instruction, as `OUT` does.
+pub trait Extension: Send {
+    fn execute(&mut self, computer: &mut Computer, operands: &[i64]) -> Option<State>;
+
+    // A copy with its own state, for a cloned dialect.
+    fn boxed_clone(&self) -> Box<dyn Extension>;
+}
+
+impl<F> Extension for F
+where
+    F: FnMut(&mut Computer, &[i64]) -> Option<State> + Clone + Send + 'static,
+{
+    fn execute(&mut self, computer: &mut Computer, operands: &[i64]) -> Option<State> {
+        self(computer, operands)
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Extension> {
+        Box::new(self.clone())
+    }
+}
+
+// Every parameter list an opcode can have is a prefix of one of these, so an
+// added opcode's parameters point into static data just as the built-ins' do.
+static SHAPES: [[ParamKind; 3]; 8] = [
+    [ParamKind::Read, ParamKind::Read, ParamKind::Read],
+    [ParamKind::Read, ParamKind::Read, ParamKind::Write],
+    [ParamKind::Read, ParamKind::Write, ParamKind::Read],
+    [ParamKind::Read, ParamKind::Write, ParamKind::Write],
+    [ParamKind::Write, ParamKind::Read, ParamKind::Read],
+    [ParamKind::Write, ParamKind::Read, ParamKind::Write],
+    [ParamKind::Write, ParamKind::Write, ParamKind::Read],
+    [ParamKind::Write, ParamKind::Write, ParamKind::Write],
+];
+
+fn shape(params: &[ParamKind]) -> &'static [ParamKind] {
+    let shape = SHAPES
+        .iter()
+        .find(|shape| shape.starts_with(params))
+        .unwrap();
+    &shape[..params.len()]
+}
+
+// Every opcode a dialect can pick from without adding its own.
+fn built_in() -> impl Iterator<Item = &'static OpcodeInfo> {
+    OPCODES.iter().chain(FUSED_OPCODES)
+}
+
+// The instruction set a `Computer` accepts. Anything outside it is an unknown
+// opcode, and a parameter mode it doesn't allow is an invalid one, so a program
+// written for an earlier day can be held to what that day defined.
+//
+// Each clone of a dialect gets its own copy of every extension, so state an
+// extension keeps is never shared between machines unless it shares it itself
+// (through an `Arc` it captured, say).
+pub struct Dialect {
+    name: String,
+    // Built-in opcodes by code, as one more than their place in `built_in()`,
+    // or zero. Every machine has a dialect, so it's kept small and cheap to
+    // create.
+    table: [u8; 100],
+    // Opcodes added with `add_opcode`.
+    added: Vec<OpcodeInfo>,
+    // Instruction lengths by opcode, for built-in opcodes only: zero marks
+    // one that's unknown or an extension.
+    lengths: [u8; 100],
+    // Indexed by mode digit.
+    modes: [bool; 3],
+    // An extension is taken out while it runs, so one that steps the machine
+    // into its own opcode again gets an unknown opcode error rather than a
+    // second borrow of itself.
+    extensions: BTreeMap<i64, Option<Box<dyn Extension>>>,
+}
+
+impl Dialect {
+    pub fn with_opcodes(name: &str, codes: &[i64]) -> Self {
+        let mut table = [0; 100];
+        let mut lengths = [0; 100];
+        for (i, info) in built_in()
+            .enumerate()
+            .filter(|(_, info)| codes.contains(&info.code))
+        {
+            table[info.code as usize] = i as u8 + 1;
+            lengths[info.code as usize] = 1 + info.params.len() as u8;
+        }
+        Self {
+            name: name.to_string(),
+            table,
+            added: Vec::new(),
+            lengths,
+            modes: [true; 3],
+            extensions: BTreeMap::new(),
+        }
+    }
+
+    // Allows only the given parameter modes; every mode is allowed otherwise.
+    pub fn with_modes(mut self, modes: &[ParamMode]) -> Self {
+        self.modes = [false; 3];
+        for mode in modes {
+            self.modes[mode.digit() as usize] = true;
+        }
+        self
+    }
+
+    // ADD, MUL and HLT, with position mode only.
+    pub fn day2() -> Self {
+        Self::with_opcodes("day 2", &[1, 2, 99]).with_modes(&[ParamMode::Position])
+    }
+
+    // Adds I/O, jumps, comparisons and immediate mode.
+    pub fn day5() -> Self {
+        Self::with_opcodes("day 5", &[1, 2, 3, 4, 5, 6, 7, 8, 99])
+            .with_modes(&[ParamMode::Position, ParamMode::Immediate])
+    }
+
+    // The complete instruction set, with relative mode and ARB.
+    pub fn day9() -> Self {
+        Self::with_opcodes("day 9", &[1, 2, 3, 4, 5, 6, 7, 8, 9, 99])
+    }
+
+    // Day 9's instructions plus the super-instructions optimized programs use.
+    pub fn fused() -> Self {
+        let codes: Vec<i64> = OPCODES
+            .iter()
+            .chain(FUSED_OPCODES)
+            .map(|info| info.code)
+            .collect();
+        Self::with_opcodes("fused", &codes)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn info(&self, code: i64) -> Option<&OpcodeInfo> {
+        match code {
+            0..=99 => match self.table[code as usize] {
+                0 => self.added.iter().find(|info| info.code == code),
+                i => built_in().nth(i as usize - 1),
+            },
+            _ => None,
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn allows(&self, mode: ParamMode) -> bool {
+        self.modes[mode.digit() as usize]
+    }
+
+    #[inline(always)]
+    pub(crate) fn builtin_len(&self, code: i64) -> Option<usize> {
+        match code {
+            0..=99 => Some(self.lengths[code as usize] as usize).filter(|&len| len > 0),
+            _ => None,
+        }
+    }
+
+    // Whether each mode is allowed, by digit.
+    pub(crate) fn modes(&self) -> [bool; 3] {
+        self.modes
+    }
+
+    pub(crate) fn is_extension(&self, code: i64) -> bool {
+        !self.extensions.is_empty() && self.extensions.contains_key(&code)
+    }
+
+    pub(crate) fn take_extension(&mut self, code: i64) -> Option<Box<dyn Extension>> {
+        self.extensions.get_mut(&code)?.take()
+    }
+
+    pub(crate) fn restore_extension(&mut self, code: i64, extension: Box<dyn Extension>) {
+        if let Some(slot) = self.extensions.get_mut(&code) {
+            *slot = Some(extension);
+        }
+    }
+
+    // Registers a new opcode. Panics if the code is outside 1..=99 or already
+    // taken, or if it has more than three parameters.
+    pub fn add_opcode<E: Extension + 'static>(
+        &mut self,
+        code: i64,
+        mnemonic: &'static str,
+        params: &[ParamKind],
+        extension: E,
+    ) {
+        assert!((1..=99).contains(&code), "opcode {} out of range", code);
+        assert!(self.info(code).is_none(), "opcode {} already defined", code);
+        assert!(
+            params.len() <= 3,
+            "{} has more than three parameters",
+            mnemonic
+        );
+        self.added.push(OpcodeInfo {
+            code,
+            mnemonic,
+            params: shape(params),
+        });
+        self.extensions.insert(code, Some(Box::new(extension)));
+    }
+}
+
+impl Clone for Dialect {
+    fn clone(&self) -> Self {
+        let extensions = self
+            .extensions
+            .iter()
+            .map(|(&code, extension)| (code, extension.as_ref().map(|e| e.boxed_clone())))
+            .collect();
+        Self {
+            name: self.name.clone(),
+            table: self.table,
+            added: self.added.clone(),
+            lengths: self.lengths,
+            modes: self.modes,
+            extensions,
+        }
+    }
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Self::day9()
+    }
+}
+
+impl fmt::Debug for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mnemonics: Vec<&str> = (0..100)
+            .filter_map(|code| self.info(code))
+            .map(|info| info.mnemonic)
+            .collect();
+        f.debug_struct("Dialect")
+            .field("name", &self.name)
+            .field("opcodes", &mnemonics)
+            .finish()
+    }
+}
+
+#[test]
+pub fn day2_rejects_later_opcodes() {
+    use crate::computer::IntcodeError;
+
+    let mut computer = Computer::new(vec![1, 5, 6, 0, 99, 2, 3]);
+    computer.set_dialect(Dialect::day2());
+    assert_eq!(computer.run(), Ok(State::Complete));
+    assert_eq!(computer.peek(0), 5);
+
+    let mut computer = Computer::new(vec![1101, 2, 3, 0, 99]);
+    computer.set_dialect(Dialect::day2());
+    assert_eq!(
+        computer.run(),
+        Err(IntcodeError::InvalidParamMode {
+            ix: 0,
+            instruction: 1101,
+            mode: 1
+        })
+    );
+    computer.set_dialect(Dialect::day5());
+    assert_eq!(computer.run(), Ok(State::Complete));
+
+    let mut computer = Computer::new(vec![204, 3, 99, 7]);
+    computer.set_dialect(Dialect::day5());
+    assert_eq!(
+        computer.run(),
+        Err(IntcodeError::InvalidParamMode {
+            ix: 0,
+            instruction: 204,
+            mode: 2
+        })
+    );
+    computer.set_dialect(Dialect::day9());
+    assert_eq!(computer.run(), Ok(State::Output(7)));
+
+    let mut computer = Computer::new(vec![1, 0, 0, 0, 109, 1, 99]);
+    computer.set_dialect(Dialect::day2());
+    assert_eq!(
+        computer.run(),
+        Err(IntcodeError::UnknownOpcode {
+            ix: 4,
+            instruction: 109
+        })
+    );
+    computer.set_dialect(Dialect::day5());
+    assert!(computer.run().is_err());
+    computer.set_dialect(Dialect::day9());
+    assert_eq!(computer.run(), Ok(State::Complete));
+}
+
+#[test]
+pub fn custom_opcodes() {
+    use std::sync::{Arc, Mutex};
+
+    // SQR squares its first parameter into its second; DBG reports a value
+    // and pauses the machine.
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut dialect = Dialect::day9();
+    dialect.add_opcode(
+        10,
+        "SQR",
+        &[ParamKind::Read, ParamKind::Write],
+        |computer: &mut Computer, operands: &[i64]| {
+            computer.write(operands[1] as usize, operands[0] * operands[0]);
+            None
+        },
+    );
+    let log = seen.clone();
+    dialect.add_opcode(
+        42,
+        "DBG",
+        &[ParamKind::Read],
+        move |_: &mut Computer, operands: &[i64]| {
+            log.lock().unwrap().push(operands[0]);
+            Some(State::Idle)
+        },
+    );
+    assert_eq!(dialect.info(42).unwrap().mnemonic, "DBG");
+
+    // SQR #7, [9]; DBG [9]; OUT [9]; HLT
+    let program = vec![110, 7, 9, 42, 9, 4, 9, 99, 0, 0];
+    let mut computer = Computer::new(program.clone());
+    computer.set_dialect(dialect.clone());
+    computer.set_history(Some(10));
+    assert_eq!(computer.run(), Ok(State::Idle));
+    assert_eq!(*seen.lock().unwrap(), [49]);
+    assert_eq!(computer.run(), Ok(State::Output(49)));
+    assert_eq!(computer.step_back(3), 3);
+    assert_eq!(computer.peek(9), 0);
+
+    let mut plain = Computer::new(program);
+    assert!(plain.run().is_err());
+}
+
+#[test]
+pub fn clones_have_their_own_extensions() {
+    // CNT outputs how many times it has run.
+    let mut count = 0;
+    let mut dialect = Dialect::day9();
+    dialect.add_opcode(11, "CNT", &[], move |_: &mut Computer, _: &[i64]| {
+        count += 1;
+        Some(State::Output(count))
+    });
+    let mut first = Computer::new(vec![11, 11, 99]);
+    first.set_dialect(dialect.clone());
+    assert_eq!(first.run(), Ok(State::Output(1)));
+    assert_eq!(first.run(), Ok(State::Output(2)));
+    let mut second = Computer::new(vec![11, 11, 99]);
+    second.set_dialect(dialect);
+    assert_eq!(second.run(), Ok(State::Output(1)));
+}
+
+#[test]
+pub fn reentrant_extension() {
+    use crate::computer::IntcodeError;
+    use std::sync::{Arc, Mutex};
+
+    // NEST steps the machine from inside itself, onto another NEST.
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let log = results.clone();
+    let mut dialect = Dialect::day9();
+    dialect.add_opcode(
+        12,
+        "NEST",
+        &[],
+        move |computer: &mut Computer, _: &[i64]| {
+            log.lock().unwrap().push(computer.step());
+            Some(State::Idle)
+        },
+    );
+    let mut computer = Computer::new(vec![12, 12, 99]);
+    computer.set_dialect(dialect);
+    assert_eq!(computer.run(), Ok(State::Idle));
+    assert_eq!(
+        *results.lock().unwrap(),
+        [Err(IntcodeError::UnknownOpcode {
+            ix: 1,
+            instruction: 12
+        })]
+    );
+    assert_eq!(computer.ix(), 1);
+}
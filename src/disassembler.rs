@@ -0,0 +1,182 @@
+use crate::computer::{opcode_info, OpcodeInfo, ParamKind, ParamMode};
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Operand {
+    pub mode: ParamMode,
+    pub value: i64,
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            ParamMode::Immediate => write!(f, "#{}", self.value),
+            ParamMode::Position => write!(f, "[{}]", self.value),
+            ParamMode::Relative if self.value < 0 => write!(f, "rb{}", self.value),
+            ParamMode::Relative => write!(f, "rb+{}", self.value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decoded {
+    Instruction {
+        mnemonic: &'static str,
+        operands: Vec<Operand>,
+    },
+    Data(i64),
+}
+
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Decoded::Instruction { mnemonic, operands } => {
+                write!(f, "{}", mnemonic)?;
+                for (i, operand) in operands.iter().enumerate() {
+                    let sep = if i == 0 { " " } else { ", " };
+                    write!(f, "{}{}", sep, operand)?;
+                }
+                Ok(())
+            }
+            Decoded::Data(value) => write!(f, "DATA {}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub address: usize,
+    pub decoded: Decoded,
+    pub raw: Vec<i64>,
+    pub executed: bool,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let raw: Vec<String> = self.raw.iter().map(|n| n.to_string()).collect();
+        let marker = if self.executed { '*' } else { ' ' };
+        let body = self.decoded.to_string();
+        write!(
+            f,
+            "{}{:>6}  {:<32} ; {}",
+            marker,
+            self.address,
+            body,
+            raw.join(",")
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing {
+    pub lines: Vec<Line>,
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn decode_at(program: &[i64], address: usize) -> Option<(&'static OpcodeInfo, Vec<Operand>)> {
+    let instruction = *program.get(address)?;
+    if instruction < 0 {
+        return None;
+    }
+    let info = opcode_info(instruction % 100)?;
+    if address + info.params.len() >= program.len() {
+        return None;
+    }
+    let mut modes = instruction / 100;
+    let mut operands = Vec::new();
+    for (i, kind) in info.params.iter().enumerate() {
+        let mode = ParamMode::from_digit(modes % 10)?;
+        if *kind == ParamKind::Write && mode == ParamMode::Immediate {
+            return None;
+        }
+        modes /= 10;
+        operands.push(Operand {
+            mode,
+            value: program[address + 1 + i],
+        });
+    }
+    Some((info, operands))
+}
+
+pub fn disassemble(program: &[i64], executed: Option<&HashSet<usize>>) -> Listing {
+    let was_executed = |address| executed.is_some_and(|set| set.contains(&address));
+    let mut lines = Vec::new();
+    let mut address = 0;
+
+    while address < program.len() {
+        let decoded = decode_at(program, address).filter(|(info, _)| {
+            // A trace that lands inside this instruction means we're out of step with the code.
+            (address + 1..=address + info.params.len()).all(|a| !was_executed(a))
+        });
+        let (decoded, width) = match decoded {
+            Some((info, operands)) => (
+                Decoded::Instruction {
+                    mnemonic: info.mnemonic,
+                    operands,
+                },
+                info.params.len() + 1,
+            ),
+            None => (Decoded::Data(program[address]), 1),
+        };
+        lines.push(Line {
+            address,
+            decoded,
+            raw: program[address..address + width].to_vec(),
+            executed: was_executed(address),
+        });
+        address += width;
+    }
+
+    Listing { lines }
+}
+
+#[test]
+pub fn simple_listing() {
+    let listing = disassemble(&[1002, 4, 3, 4, 33], None);
+    assert_eq!(listing.lines.len(), 2);
+    assert_eq!(listing.lines[0].decoded.to_string(), "MUL [4], #3, [4]");
+    assert_eq!(listing.lines[1].decoded, Decoded::Data(33));
+    assert_eq!(listing.lines[1].address, 4);
+}
+
+#[test]
+pub fn relative_operands() {
+    let listing = disassemble(&[109, -1, 204, 1, 99], None);
+    let bodies: Vec<String> = listing
+        .lines
+        .iter()
+        .map(|l| l.decoded.to_string())
+        .collect();
+    assert_eq!(bodies, ["ARB #-1", "OUT rb+1", "HLT"]);
+}
+
+#[test]
+pub fn bad_modes_fall_back_to_data() {
+    let listing = disassemble(&[11101, 1, 1, 5], None);
+    assert_eq!(listing.lines[0].decoded, Decoded::Data(11101));
+    let listing = disassemble(&[304, 1, 99], None);
+    assert_eq!(listing.lines[0].decoded, Decoded::Data(304));
+    assert_eq!(listing.lines[2].decoded.to_string(), "HLT");
+}
+
+#[test]
+pub fn trace_resyncs_and_marks() {
+    // Jumps over the data word at 3 into the middle of what a linear sweep sees as ADD.
+    let program = [1105, 1, 4, 1, 104, 7, 99];
+    let executed: HashSet<usize> = [0, 4, 6].iter().cloned().collect();
+    let listing = disassemble(&program, Some(&executed));
+    let text = listing.to_string();
+    assert!(text.contains("*     0  JT #1, #4"));
+    assert!(text.contains("      3  DATA 1"));
+    assert!(text.contains("*     4  OUT #7"));
+    assert!(text.contains("*     6  HLT"));
+}
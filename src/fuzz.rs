@@ -0,0 +1,404 @@
+use crate::computer::{Arithmetic, Computer, Engine, State};
+use crate::memory::PagedMemory;
+use std::fmt;
+
+// xorshift64*: small, fast and reproducible from a seed, which is all a fuzzer
+// needs.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // A zero state would stay zero forever.
+        Self {
+            state: seed ^ 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // Uniform in 0..n.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // Uniform in lo..=hi.
+    pub fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        lo + (self.next_u64() % (hi - lo + 1) as u64) as i64
+    }
+
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+}
+
+// How a run ended. Faults only record where they happened, since the
+// reference doesn't try to classify them the way `IntcodeError` does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum End {
+    Halted,
+    AwaitingInput,
+    OutOfSteps,
+    Fault(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub end: End,
+    pub outputs: Vec<i64>,
+    // Only the words that aren't zero, so backends that grow memory differently
+    // agree and a far write doesn't mean a huge comparison.
+    pub memory: Vec<(usize, i64)>,
+    pub steps: u64,
+}
+
+// A deliberately plain interpreter to check `Computer` against: no decoding
+// table, no caching, checked arithmetic, and every instruction fully resolved
+// before it has any effect.
+pub fn reference(program: &[i64], inputs: &[i64], step_limit: u64) -> Outcome {
+    let mut memory = program.to_vec();
+    let mut inputs = inputs.iter();
+    let mut outputs = Vec::new();
+    let (mut ix, mut rb, mut steps) = (0usize, 0i64, 0u64);
+
+    let end = loop {
+        if steps >= step_limit {
+            break End::OutOfSteps;
+        }
+        let word = |memory: &Vec<i64>, address: usize| memory.get(address).cloned().unwrap_or(0);
+        let instruction = word(&memory, ix);
+        let opcode = instruction % 100;
+        let (reads, writes) = match opcode {
+            1 | 2 | 7 | 8 => (2, 1),
+            3 => (0, 1),
+            4 | 9 => (1, 0),
+            5 | 6 => (2, 0),
+            99 => (0, 0),
+            _ => break End::Fault(ix),
+        };
+        // Resolve every parameter to an address (None for immediates).
+        let mut addresses = Vec::new();
+        let mut modes = instruction / 100;
+        let mut fault = false;
+        for i in 0..reads + writes {
+            let arg = word(&memory, ix + 1 + i);
+            let address = match (modes % 10, i >= reads) {
+                (0, _) => Some(arg),
+                (1, false) => None,
+                (2, _) => match arg.checked_add(rb) {
+                    Some(address) => Some(address),
+                    None => {
+                        fault = true;
+                        break;
+                    }
+                },
+                _ => {
+                    fault = true;
+                    break;
+                }
+            };
+            if address.is_some_and(|address| address < 0) {
+                fault = true;
+                break;
+            }
+            addresses.push(address.map(|address| address as usize));
+            modes /= 10;
+        }
+        if fault {
+            break End::Fault(ix);
+        }
+        let args: Vec<i64> = (0..reads)
+            .map(|i| match addresses[i] {
+                Some(address) => word(&memory, address),
+                None => word(&memory, ix + 1 + i),
+            })
+            .collect();
+        let target = addresses.get(reads).cloned().flatten();
+        let next = ix + 1 + reads + writes;
+        let result = match opcode {
+            1 => args[0].checked_add(args[1]),
+            2 => args[0].checked_mul(args[1]),
+            7 => Some((args[0] < args[1]) as i64),
+            8 => Some((args[0] == args[1]) as i64),
+            3 => match inputs.next() {
+                Some(&input) => Some(input),
+                None => break End::AwaitingInput,
+            },
+            _ => None,
+        };
+        match opcode {
+            1 | 2 | 3 | 7 | 8 => {
+                let value = match result {
+                    Some(value) => value,
+                    None => break End::Fault(ix),
+                };
+                let target = target.unwrap();
+                if target >= memory.len() {
+                    memory.resize(target + 1, 0);
+                }
+                memory[target] = value;
+                ix = next;
+            }
+            4 => {
+                outputs.push(args[0]);
+                ix = next;
+            }
+            5 | 6 => {
+                if (args[0] != 0) == (opcode == 5) {
+                    if args[1] < 0 || args[1] as usize >= memory.len() {
+                        break End::Fault(ix);
+                    }
+                    ix = args[1] as usize;
+                } else {
+                    ix = next;
+                }
+            }
+            9 => {
+                rb = match rb.checked_add(args[0]) {
+                    Some(rb) => rb,
+                    None => break End::Fault(ix),
+                };
+                ix = next;
+            }
+            _ => {
+                steps += 1;
+                break End::Halted;
+            }
+        }
+        steps += 1;
+    };
+    Outcome {
+        end,
+        outputs,
+        memory: crate::memory::Memory::nonzero(&memory).collect(),
+        steps,
+    }
+}
+
+pub const CONFIGS: &[(&str, Engine, bool)] = &[
+    ("interpreter, dense", Engine::Interpreter, false),
+    ("interpreter, paged", Engine::Interpreter, true),
+    ("cached, dense", Engine::Cached, false),
+    ("cached, paged", Engine::Cached, true),
+];
+
+// Runs `Computer` in checked mode with the given engine and memory backend.
+pub fn run_computer(
+    program: &[i64],
+    inputs: &[i64],
+    step_limit: u64,
+    engine: Engine,
+    paged: bool,
+) -> Outcome {
+    let mut computer = match paged {
+        true => Computer::with_memory(PagedMemory::new(program)),
+        false => Computer::new(program.to_vec()),
+    };
+    computer.set_engine(engine);
+    computer.set_arithmetic(Arithmetic::Checked);
+    computer.set_step_limit(Some(step_limit));
+    for &input in inputs {
+        computer.add_input(input);
+    }
+    outcome(&mut computer)
+}
+
+// Runs `computer` until it halts, faults, waits for input or runs out of steps.
+pub fn outcome(computer: &mut Computer) -> Outcome {
+    let mut outputs = Vec::new();
+    let end = loop {
+        match computer.run() {
+            Ok(State::Output(value)) => outputs.push(value),
+            Ok(State::Complete) => break End::Halted,
+            Ok(State::AwaitingInput) => break End::AwaitingInput,
+            Ok(State::BudgetExhausted) => break End::OutOfSteps,
+            Ok(state) => panic!("unexpected {:?}", state),
+            Err(err) => break End::Fault(err.ix()),
+        }
+    };
+    Outcome {
+        end,
+        outputs,
+        memory: computer.memory.nonzero().collect(),
+        steps: computer.cycles(),
+    }
+}
+
+// Where the generator puts each instruction, before operands are filled in.
+const OPCODE_WEIGHTS: &[(i64, usize)] = &[
+    (1, 6),
+    (2, 4),
+    (3, 2),
+    (4, 3),
+    (5, 3),
+    (6, 3),
+    (7, 3),
+    (8, 3),
+    (9, 2),
+    (99, 1),
+];
+
+fn pick_opcode(rng: &mut Rng) -> i64 {
+    let total: usize = OPCODE_WEIGHTS.iter().map(|&(_, weight)| weight).sum();
+    let mut roll = rng.below(total);
+    for &(opcode, weight) in OPCODE_WEIGHTS {
+        if roll < weight {
+            return opcode;
+        }
+        roll -= weight;
+    }
+    unreachable!()
+}
+
+const DATA_LEN: usize = 16;
+
+// A random program that is well formed where it counts: valid modes, jumps to
+// instruction boundaries, and addresses that mostly land in a data area after
+// the code, with the relative base pointed at it. Occasional writes into the
+// code make it self-modifying, and loops are bounded by the step limit.
+pub fn generate(rng: &mut Rng) -> (Vec<i64>, Vec<i64>) {
+    let count = rng.range(4, 40) as usize;
+    let mut opcodes = vec![9];
+    opcodes.extend((0..count).map(|_| pick_opcode(rng)));
+    opcodes.push(99);
+    let mut starts = Vec::new();
+    let mut len = 0;
+    for &opcode in &opcodes {
+        starts.push(len);
+        len += 1 + arity(opcode);
+    }
+    let data = len;
+
+    let mut program = Vec::with_capacity(len + DATA_LEN);
+    for (i, &opcode) in opcodes.iter().enumerate() {
+        if i == 0 {
+            program.extend(&[109, data as i64]);
+            continue;
+        }
+        let reads = arity(opcode) - writes(opcode);
+        let mut instruction = opcode;
+        let mut operands = Vec::new();
+        for p in 0..arity(opcode) {
+            let write = p >= reads;
+            let jump_target = (opcode == 5 || opcode == 6) && p == 1;
+            let mode = match rng.below(if write { 2 } else { 3 }) {
+                0 => 0,
+                1 if write => 2,
+                1 => 1,
+                _ => 2,
+            };
+            let operand = match mode {
+                1 if jump_target => starts[rng.below(starts.len())] as i64,
+                1 if opcode == 9 => rng.range(-3, 3),
+                1 => rng.range(-20, 20),
+                // Stray into the code now and then.
+                0 if write && rng.chance(5) => rng.below(data) as i64,
+                0 => (data + rng.below(DATA_LEN)) as i64,
+                _ => rng.range(-2, DATA_LEN as i64 + 1),
+            };
+            instruction += mode * 10i64.pow(p as u32 + 2);
+            operands.push(operand);
+        }
+        program.push(instruction);
+        program.extend(operands);
+    }
+    program.extend((0..DATA_LEN).map(|_| rng.range(-5, 5)));
+    let inputs = (0..rng.below(6)).map(|_| rng.range(-10, 10)).collect();
+    (program, inputs)
+}
+
+fn arity(opcode: i64) -> usize {
+    match opcode {
+        1 | 2 | 7 | 8 => 3,
+        5 | 6 => 2,
+        3 | 4 | 9 => 1,
+        _ => 0,
+    }
+}
+
+fn writes(opcode: i64) -> usize {
+    match opcode {
+        1 | 2 | 3 | 7 | 8 => 1,
+        _ => 0,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub config: &'static str,
+    pub program: Vec<i64>,
+    pub inputs: Vec<i64>,
+    pub expected: Outcome,
+    pub actual: Outcome,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} disagrees with the reference", self.config)?;
+        writeln!(f, "program: {:?}", self.program)?;
+        writeln!(f, "inputs: {:?}", self.inputs)?;
+        writeln!(f, "expected: {:?}", self.expected)?;
+        write!(f, "actual: {:?}", self.actual)
+    }
+}
+
+pub const STEP_LIMIT: u64 = 2_000;
+
+pub fn check(program: &[i64], inputs: &[i64]) -> Result<Outcome, Box<Mismatch>> {
+    let expected = reference(program, inputs, STEP_LIMIT);
+    for &(config, engine, paged) in CONFIGS {
+        let actual = run_computer(program, inputs, STEP_LIMIT, engine, paged);
+        if actual != expected {
+            return Err(Box::new(Mismatch {
+                config,
+                program: program.to_vec(),
+                inputs: inputs.to_vec(),
+                expected,
+                actual,
+            }));
+        }
+    }
+    Ok(expected)
+}
+
+// Checks `cases` generated programs, returning every outcome or the first mismatch.
+pub fn fuzz(seed: u64, cases: usize) -> Result<Vec<Outcome>, Box<Mismatch>> {
+    let mut rng = Rng::new(seed);
+    (0..cases)
+        .map(|_| {
+            let (program, inputs) = generate(&mut rng);
+            check(&program, &inputs)
+        })
+        .collect()
+}
+
+#[test]
+pub fn vm_matches_reference() {
+    let outcomes = match fuzz(2019, 500) {
+        Ok(outcomes) => outcomes,
+        Err(mismatch) => panic!("{}", mismatch),
+    };
+    // The generator should reach every kind of ending, or it isn't testing much.
+    let count = |pred: fn(&End) -> bool| outcomes.iter().filter(|o| pred(&o.end)).count();
+    assert!(count(|end| *end == End::Halted) > 10);
+    assert!(count(|end| *end == End::AwaitingInput) > 10);
+    assert!(count(|end| *end == End::OutOfSteps) > 10);
+    assert!(count(|end| matches!(end, End::Fault(_))) > 10);
+}
+
+#[test]
+pub fn self_modifying_program() {
+    // The first instruction turns the HLT at 4 into an OUT of itself.
+    let program = [1101, 3, 1, 4, 99, 4, 99];
+    let outcome = check(&program, &[]).unwrap();
+    assert_eq!(outcome.outputs, [4]);
+    assert_eq!(outcome.end, End::Halted);
+    assert_eq!(outcome.steps, 3);
+}
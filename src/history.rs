@@ -0,0 +1,66 @@
+use crate::bignum::BigInt;
+use std::collections::VecDeque;
+
+// What one executed instruction changed, so that it can be undone: the words it
+// overwrote (with any big-mode value they held), the input it consumed, and
+// the registers before it ran.
+#[derive(Debug, Clone)]
+pub(crate) struct Undo {
+    pub ix: usize,
+    pub relative_base: i64,
+    pub instruction: i64,
+    pub writes: Vec<(usize, i64, Option<BigInt>)>,
+    pub input: Option<i64>,
+}
+
+// A bounded undo log. Once full, the oldest instructions can no longer be undone.
+#[derive(Debug, Clone)]
+pub(crate) struct History {
+    entries: VecDeque<Undo>,
+    capacity: usize,
+    // Overwritten words for the instruction in progress.
+    pub writes: Vec<(usize, i64, Option<BigInt>)>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+            writes: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, undo: Undo) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(undo);
+    }
+
+    pub fn pop(&mut self) -> Option<Undo> {
+        self.entries.pop_back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.writes.clear();
+    }
+
+    // How many instructions back the most recent one with the given opcode is,
+    // counting it.
+    pub fn distance_to(&self, opcode: i64) -> Option<usize> {
+        self.entries
+            .iter()
+            .rev()
+            .position(|undo| undo.instruction % 100 == opcode)
+            .map(|i| i + 1)
+    }
+}
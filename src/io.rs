@@ -0,0 +1,150 @@
+use crate::computer::{Computer, IntcodeError, State};
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+// A source of input values. `None` means nothing is available (or ever will be,
+// for blocking sources whose producers have gone away).
+pub trait Input {
+    fn next_input(&mut self) -> Option<i64>;
+}
+
+pub trait Output {
+    fn put(&mut self, value: i64);
+}
+
+impl Input for VecDeque<i64> {
+    fn next_input(&mut self) -> Option<i64> {
+        self.pop_front()
+    }
+}
+
+impl Output for VecDeque<i64> {
+    fn put(&mut self, value: i64) {
+        self.push_back(value);
+    }
+}
+
+impl Output for Vec<i64> {
+    fn put(&mut self, value: i64) {
+        self.push(value);
+    }
+}
+
+// Blocks until a value arrives or every sender has been dropped.
+impl Input for Receiver<i64> {
+    fn next_input(&mut self) -> Option<i64> {
+        self.recv().ok()
+    }
+}
+
+// A receiver that has gone away just means nobody is listening any more.
+impl Output for Sender<i64> {
+    fn put(&mut self, value: i64) {
+        self.send(value).ok();
+    }
+}
+
+pub struct FnInput<F: FnMut() -> Option<i64>>(pub F);
+
+impl<F: FnMut() -> Option<i64>> Input for FnInput<F> {
+    fn next_input(&mut self) -> Option<i64> {
+        (self.0)()
+    }
+}
+
+pub struct FnOutput<F: FnMut(i64)>(pub F);
+
+impl<F: FnMut(i64)> Output for FnOutput<F> {
+    fn put(&mut self, value: i64) {
+        (self.0)(value)
+    }
+}
+
+impl Computer {
+    // Drives the machine against the given ports until it halts, runs out of
+    // input, or stops for some other reason (watchpoint, budget).
+    pub fn run_with<I: Input, O: Output>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<State, IntcodeError> {
+        loop {
+            match self.run()? {
+                State::Output(value) => output.put(value),
+                State::AwaitingInput => match input.next_input() {
+                    Some(value) => self.add_input(value),
+                    None => return Ok(State::AwaitingInput),
+                },
+                state => return Ok(state),
+            }
+        }
+    }
+}
+
+// Runs a machine on its own thread, handing back the machine and its ports once it stops.
+pub fn spawn<I, O>(
+    mut computer: Computer,
+    mut input: I,
+    mut output: O,
+) -> JoinHandle<(Computer, Result<State, IntcodeError>, I, O)>
+where
+    I: Input + Send + 'static,
+    O: Output + Send + 'static,
+{
+    thread::spawn(move || {
+        let result = computer.run_with(&mut input, &mut output);
+        (computer, result, input, output)
+    })
+}
+
+#[test]
+pub fn queues_and_closures() {
+    let mut computer = Computer::new(vec![3, 9, 1002, 9, 3, 9, 4, 9, 99, 0]);
+    let mut outputs = Vec::new();
+    let result = computer.run_with(&mut VecDeque::new(), &mut outputs);
+    assert_eq!(result, Ok(State::AwaitingInput));
+
+    let mut n = 0;
+    let mut input = FnInput(|| {
+        n += 1;
+        Some(n * 7)
+    });
+    let mut seen = Vec::new();
+    let mut output = FnOutput(|v| seen.push(v));
+    assert_eq!(
+        computer.run_with(&mut input, &mut output),
+        Ok(State::Complete)
+    );
+    assert_eq!(seen, [21]);
+}
+
+#[test]
+pub fn threaded_feedback_loop() {
+    use std::sync::mpsc::channel;
+
+    let program = crate::samples::FEEDBACK_LOOP.to_vec();
+    let phases = [9, 8, 7, 6, 5];
+    let (senders, receivers): (Vec<_>, Vec<_>) = phases.iter().map(|_| channel()).unzip();
+    for (sender, phase) in senders.iter().zip(&phases) {
+        sender.send(*phase).unwrap();
+    }
+    senders[0].send(0).unwrap();
+
+    let handles: Vec<_> = receivers
+        .into_iter()
+        .enumerate()
+        .map(|(i, receiver)| {
+            let next = senders[(i + 1) % phases.len()].clone();
+            spawn(Computer::new(program.clone()), receiver, next)
+        })
+        .collect();
+    drop(senders);
+
+    let mut finished: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    for (_, result, _, _) in &finished {
+        assert_eq!(*result, Ok(State::Complete));
+    }
+    let (_, _, first_input, _) = &mut finished[0];
+    assert_eq!(first_input.try_iter().last(), Some(139629729));
+}
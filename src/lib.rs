@@ -20,6 +20,30 @@ pub mod day7;
 pub mod day8;
 pub mod day9;
 
+mod ascii;
+mod assembler;
+mod bignum;
+mod calls;
+mod cfg;
 mod computer;
+mod coverage;
+mod debugger;
+mod dialect;
+mod disassembler;
+mod fuzz;
+mod history;
+mod io;
+mod memory;
+mod network;
+mod optimizer;
+mod profile;
+mod samples;
+mod search;
+mod snapshot;
+mod symbolic;
+mod trace;
+
+pub use ascii::play_stdio;
+pub use debugger::debug_stdio;
 
 aoc_lib! { year = 2019 }
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::fmt;
+
+// Backing store for a `Computer`. Addresses that were never written read as zero.
+pub trait Memory: fmt::Debug {
+    fn get(&self, address: usize) -> i64;
+    fn set(&mut self, address: usize, value: i64);
+    // One past the highest address that has been loaded or written.
+    fn len(&self) -> usize;
+    // Words that would be held after a write to `address`, for enforcing limits.
+    fn footprint_after(&self, address: usize) -> usize;
+    // Replaces the whole contents.
+    fn load(&mut self, words: &[i64]);
+    // Replaces the whole contents with `words`, as `(address, value)` pairs,
+    // and zeros up to `len`.
+    fn load_sparse(&mut self, len: usize, words: &[(usize, i64)]);
+    // Every word up to `len`, zeros included; sparse backends fill in the gaps.
+    fn to_vec(&self) -> Vec<i64>;
+    // Words that aren't zero, in address order, without visiting the gaps
+    // between them.
+    fn nonzero(&self) -> Box<dyn Iterator<Item = (usize, i64)> + '_>;
+    // Every word up to `len` as one slice, for backends that keep them that way.
+    fn dense_mut(&mut self) -> Option<&mut [i64]> {
+        None
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// Dense memory: writing to an address allocates every word below it.
+impl Memory for Vec<i64> {
+    fn get(&self, address: usize) -> i64 {
+        self.as_slice().get(address).cloned().unwrap_or(0)
+    }
+
+    fn set(&mut self, address: usize, value: i64) {
+        if address >= self.len() {
+            self.resize(address + 1, 0);
+        }
+        self[address] = value;
+    }
+
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn footprint_after(&self, address: usize) -> usize {
+        self.as_slice().len().max(address + 1)
+    }
+
+    fn load(&mut self, words: &[i64]) {
+        self.clear();
+        self.extend_from_slice(words);
+    }
+
+    fn load_sparse(&mut self, len: usize, words: &[(usize, i64)]) {
+        self.clear();
+        self.resize(len, 0);
+        for &(address, value) in words {
+            Memory::set(self, address, value);
+        }
+    }
+
+    fn to_vec(&self) -> Vec<i64> {
+        self.clone()
+    }
+
+    fn nonzero(&self) -> Box<dyn Iterator<Item = (usize, i64)> + '_> {
+        Box::new(
+            self.iter()
+                .enumerate()
+                .filter(|&(_, &value)| value != 0)
+                .map(|(address, &value)| (address, value)),
+        )
+    }
+
+    fn dense_mut(&mut self) -> Option<&mut [i64]> {
+        Some(self)
+    }
+}
+
+const PAGE_SIZE: usize = 1024;
+
+// Sparse memory: only pages that have been written are allocated, so a stray
+// write far past the program costs one page rather than everything in between.
+#[derive(Debug, Clone, Default)]
+pub struct PagedMemory {
+    pages: HashMap<usize, Box<[i64]>>,
+    len: usize,
+}
+
+impl PagedMemory {
+    pub fn new(program: &[i64]) -> Self {
+        let mut memory = Self::default();
+        memory.load(program);
+        memory
+    }
+
+    pub fn pages(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+impl Memory for PagedMemory {
+    fn get(&self, address: usize) -> i64 {
+        self.pages
+            .get(&(address / PAGE_SIZE))
+            .map_or(0, |page| page[address % PAGE_SIZE])
+    }
+
+    fn set(&mut self, address: usize, value: i64) {
+        let page = self
+            .pages
+            .entry(address / PAGE_SIZE)
+            .or_insert_with(|| vec![0; PAGE_SIZE].into_boxed_slice());
+        page[address % PAGE_SIZE] = value;
+        self.len = self.len.max(address + 1);
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn footprint_after(&self, address: usize) -> usize {
+        let mut pages = self.pages.len();
+        if !self.pages.contains_key(&(address / PAGE_SIZE)) {
+            pages += 1;
+        }
+        pages * PAGE_SIZE
+    }
+
+    fn load(&mut self, words: &[i64]) {
+        self.pages.clear();
+        self.len = 0;
+        for (address, &word) in words.iter().enumerate() {
+            self.set(address, word);
+        }
+    }
+
+    fn load_sparse(&mut self, len: usize, words: &[(usize, i64)]) {
+        self.pages.clear();
+        self.len = 0;
+        for &(address, value) in words {
+            self.set(address, value);
+        }
+        self.len = len.max(self.len);
+    }
+
+    fn to_vec(&self) -> Vec<i64> {
+        (0..self.len).map(|address| self.get(address)).collect()
+    }
+
+    fn nonzero(&self) -> Box<dyn Iterator<Item = (usize, i64)> + '_> {
+        let mut pages: Vec<_> = self.pages.iter().collect();
+        pages.sort_unstable_by_key(|&(&page, _)| page);
+        Box::new(pages.into_iter().flat_map(|(&page, words)| {
+            words
+                .iter()
+                .enumerate()
+                .filter(|&(_, &value)| value != 0)
+                .map(move |(offset, &value)| (page * PAGE_SIZE + offset, value))
+        }))
+    }
+}
+
+impl PartialEq<&[i64]> for Box<dyn Memory + Send> {
+    fn eq(&self, other: &&[i64]) -> bool {
+        self.len() == other.len() && other.iter().enumerate().all(|(i, &v)| self.get(i) == v)
+    }
+}
+
+impl PartialEq<Vec<i64>> for Box<dyn Memory + Send> {
+    fn eq(&self, other: &Vec<i64>) -> bool {
+        *self == other.as_slice()
+    }
+}
+
+#[test]
+pub fn paged_reads_and_writes() {
+    let mut memory = PagedMemory::new(&[1, 2, 3]);
+    assert_eq!(memory.pages(), 1);
+    memory.set(5 * PAGE_SIZE + 7, -4);
+    assert_eq!(memory.pages(), 2);
+    assert_eq!(memory.get(5 * PAGE_SIZE + 7), -4);
+    assert_eq!(memory.get(3 * PAGE_SIZE), 0);
+    assert_eq!(memory.len(), 5 * PAGE_SIZE + 8);
+    assert_eq!(&memory.to_vec()[..4], &[1, 2, 3, 0]);
+    assert_eq!(
+        memory.nonzero().collect::<Vec<_>>(),
+        [(0, 1), (1, 2), (2, 3), (5 * PAGE_SIZE + 7, -4)]
+    );
+    memory.load(&[9]);
+    assert_eq!(memory.to_vec(), [9]);
+    memory.load_sparse(3 * PAGE_SIZE, &[(2 * PAGE_SIZE, 5)]);
+    assert_eq!((memory.pages(), memory.len()), (1, 3 * PAGE_SIZE));
+}
+
+#[test]
+pub fn far_relative_write() {
+    use crate::computer::{Computer, IntcodeError, State};
+
+    // Stores its input a billion words out, then echoes it back.
+    let program = [109, 1_000_000_000, 203, 0, 204, 0, 99];
+    let limit = Some(4 * PAGE_SIZE);
+
+    let mut paged = Computer::with_memory(PagedMemory::new(&program));
+    paged.set_memory_limit(limit);
+    paged.add_input(42);
+    assert_eq!(paged.run(), Ok(State::Output(42)));
+    assert_eq!(paged.run(), Ok(State::Complete));
+    // Snapshots only hold the words that are set.
+    let snapshot = paged.snapshot();
+    assert_eq!(snapshot.memory.len(), 6);
+    let mut restored = Computer::from_snapshot(snapshot.clone());
+    assert_eq!(restored.peek(1_000_000_000), 42);
+    assert_eq!(restored.snapshot(), snapshot);
+
+    let mut dense = Computer::new(program.to_vec());
+    dense.set_memory_limit(limit);
+    dense.add_input(42);
+    assert_eq!(
+        dense.run(),
+        Err(IntcodeError::MemoryLimit {
+            ix: 2,
+            instruction: 203,
+            address: 1_000_000_000
+        })
+    );
+    assert_eq!(dense.memory.len(), program.len());
+}
+
+#[test]
+pub fn limit_holds_without_a_tracer() {
+    use crate::computer::{Computer, IntcodeError, TraceEvent};
+
+    // Eight words against a limit of four: the first write is refused whether
+    // or not anything is watching.
+    let program = vec![1101, 2, 3, 7, 1105, 1, 0, 0];
+    let expected = Err(IntcodeError::MemoryLimit {
+        ix: 0,
+        instruction: 1101,
+        address: 7,
+    });
+    let mut plain = Computer::new(program.clone());
+    plain.set_memory_limit(Some(4));
+    assert_eq!(plain.run(), expected);
+    let mut traced = Computer::new(program);
+    traced.set_memory_limit(Some(4));
+    traced.set_tracer(Vec::<TraceEvent>::new());
+    assert_eq!(traced.run(), expected);
+}
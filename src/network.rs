@@ -0,0 +1,433 @@
+use crate::computer::{Computer, IntcodeError, State};
+use crate::snapshot::Snapshot;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachineError {
+    pub machine: usize,
+    pub error: IntcodeError,
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "machine {}: {}", self.machine, self.error)
+    }
+}
+
+impl std::error::Error for MachineError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachineStatus {
+    pub machine: usize,
+    pub ix: usize,
+    pub halted: bool,
+    pub pending: Vec<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub machines: Vec<MachineStatus>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for status in &self.machines {
+            if status.halted {
+                writeln!(f, "machine {}: halted at {}", status.machine, status.ix)?;
+            } else {
+                writeln!(
+                    f,
+                    "machine {}: at {}, pending input {:?}",
+                    status.machine, status.ix, status.pending
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    AllHalted,
+    // Every machine still running is waiting on an empty queue.
+    Deadlock(Diagnostic),
+    // The whole network came back to a state it had already been in.
+    Livelock(Diagnostic),
+    // A machine stopped for a reason the scheduler can't resolve (watchpoint, budget).
+    Interrupted(usize, State),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Report {
+    pub outcome: Outcome,
+    pub outputs: Vec<Vec<i64>>,
+    pub halt_order: Vec<usize>,
+    pub sinks: Vec<usize>,
+}
+
+impl Report {
+    pub fn sink_outputs(&self) -> Vec<(usize, &[i64])> {
+        self.sinks
+            .iter()
+            .map(|&id| (id, self.outputs[id].as_slice()))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct Node {
+    computer: Computer,
+    queue: VecDeque<i64>,
+    targets: Vec<usize>,
+    halted: bool,
+}
+
+// Machines are scheduled round-robin in the order they were added, each running
+// until it blocks on input, so a given topology always plays out the same way.
+#[derive(Debug, Default)]
+pub struct Network {
+    nodes: Vec<Node>,
+    time_slice: Option<u64>,
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            time_slice: None,
+        }
+    }
+
+    // With a time slice, machines are also preempted after that many instructions
+    // and the network's global state is checked for repeats after every round,
+    // which catches machines spinning without ever touching their input.
+    pub fn set_livelock_detection(&mut self, time_slice: Option<u64>) {
+        self.time_slice = time_slice;
+    }
+
+    pub fn add(&mut self, computer: Computer) -> usize {
+        self.add_with_inputs(computer, &[])
+    }
+
+    pub fn add_with_inputs(&mut self, computer: Computer, inputs: &[i64]) -> usize {
+        self.nodes.push(Node {
+            computer,
+            queue: inputs.iter().cloned().collect(),
+            targets: Vec::new(),
+            halted: false,
+        });
+        self.nodes.len() - 1
+    }
+
+    pub fn connect(&mut self, from: usize, to: usize) {
+        self.nodes[from].targets.push(to);
+    }
+
+    pub fn chain(&mut self, ids: &[usize]) {
+        for pair in ids.windows(2) {
+            self.connect(pair[0], pair[1]);
+        }
+    }
+
+    pub fn ring(&mut self, ids: &[usize]) {
+        self.chain(ids);
+        if let (Some(&last), Some(&first)) = (ids.last(), ids.first()) {
+            self.connect(last, first);
+        }
+    }
+
+    pub fn feed(&mut self, id: usize, value: i64) {
+        self.nodes[id].queue.push_back(value);
+    }
+
+    pub fn computer(&self, id: usize) -> &Computer {
+        &self.nodes[id].computer
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let machines = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(machine, node)| MachineStatus {
+                machine,
+                ix: node.computer.ix(),
+                halted: node.halted,
+                pending: node
+                    .computer
+                    .inputs()
+                    .iter()
+                    .chain(node.queue.iter())
+                    .cloned()
+                    .collect(),
+            })
+            .collect();
+        Diagnostic { machines }
+    }
+
+    // Memories, instruction pointers, relative bases and queued input: everything
+    // that decides what the network does next, with memory held sparsely.
+    fn state(&self) -> Vec<(Snapshot, VecDeque<i64>, bool)> {
+        self.nodes
+            .iter()
+            .map(|node| (node.computer.snapshot(), node.queue.clone(), node.halted))
+            .collect()
+    }
+
+    pub fn run(&mut self) -> Result<Report, MachineError> {
+        let mut outputs = vec![Vec::new(); self.nodes.len()];
+        let mut halt_order = Vec::new();
+        // Only a hash of each round's state is kept. A matching hash is
+        // confirmed by keeping that one state whole and checking it comes
+        // round again after the same number of rounds, which a real repeat
+        // of a deterministic network always does.
+        let mut seen = HashMap::new();
+        let mut suspect = None;
+        let mut round = 0;
+        let mut produced = Vec::new();
+
+        let outcome = loop {
+            let mut progressed = false;
+            let mut interrupted = None;
+
+            for (id, sent) in outputs.iter_mut().enumerate() {
+                let node = &mut self.nodes[id];
+                if node.halted {
+                    continue;
+                }
+                while let Some(value) = node.queue.pop_front() {
+                    node.computer.add_input(value);
+                    progressed = true;
+                }
+                let limit = node.computer.step_limit();
+                let mut preempt_at = None;
+                if let Some(slice) = self.time_slice {
+                    let turn_end = node.computer.cycles() + slice;
+                    if limit.is_none_or(|limit| turn_end < limit) {
+                        preempt_at = Some(turn_end);
+                        node.computer.set_step_limit(preempt_at);
+                    }
+                }
+                let start = node.computer.cycles();
+                let result = loop {
+                    let state = match node.computer.run() {
+                        Ok(state) => state,
+                        Err(error) => break Err(MachineError { machine: id, error }),
+                    };
+                    match state {
+                        State::Output(value) => produced.push(value),
+                        State::AwaitingInput => break Ok(()),
+                        State::Complete => {
+                            node.halted = true;
+                            halt_order.push(id);
+                            progressed = true;
+                            break Ok(());
+                        }
+                        State::BudgetExhausted if preempt_at.is_some() => break Ok(()),
+                        state => {
+                            interrupted = Some((id, state));
+                            break Ok(());
+                        }
+                    }
+                };
+                if preempt_at.is_some() {
+                    node.computer.set_step_limit(limit);
+                    progressed |= node.computer.cycles() != start;
+                }
+                result?;
+                progressed |= !produced.is_empty();
+                for value in produced.drain(..) {
+                    sent.push(value);
+                    for i in 0..self.nodes[id].targets.len() {
+                        let target = self.nodes[id].targets[i];
+                        self.nodes[target].queue.push_back(value);
+                    }
+                }
+                if interrupted.is_some() {
+                    break;
+                }
+            }
+
+            if let Some((id, state)) = interrupted {
+                break Outcome::Interrupted(id, state);
+            }
+            if self.nodes.iter().all(|node| node.halted) {
+                break Outcome::AllHalted;
+            }
+            if !progressed {
+                break Outcome::Deadlock(self.diagnostic());
+            }
+            if self.time_slice.is_some() {
+                round += 1;
+                let state = self.state();
+                if let Some((due, repeat)) = &suspect {
+                    if round == *due {
+                        if state == *repeat {
+                            break Outcome::Livelock(self.diagnostic());
+                        }
+                        suspect = None;
+                    }
+                }
+                let mut hasher = DefaultHasher::new();
+                state.hash(&mut hasher);
+                if let Some(earlier) = seen.insert(hasher.finish(), round) {
+                    if suspect.is_none() {
+                        suspect = Some((2 * round - earlier, state));
+                    }
+                }
+            }
+        };
+
+        let sinks = (0..self.nodes.len())
+            .filter(|&id| self.nodes[id].targets.is_empty())
+            .collect();
+        Ok(Report {
+            outcome,
+            outputs,
+            halt_order,
+            sinks,
+        })
+    }
+}
+
+#[test]
+pub fn amplifier_chain() {
+    let program = crate::samples::AMPLIFIER.to_vec();
+    let mut network = Network::new();
+    let amps: Vec<usize> = [4, 3, 2, 1, 0]
+        .iter()
+        .map(|&phase| network.add_with_inputs(Computer::new(program.clone()), &[phase]))
+        .collect();
+    network.chain(&amps);
+    network.feed(amps[0], 0);
+    let report = network.run().unwrap();
+    assert_eq!(report.outcome, Outcome::AllHalted);
+    assert_eq!(report.halt_order, amps);
+    assert_eq!(report.sink_outputs(), [(amps[4], &[43210][..])]);
+}
+
+#[test]
+pub fn amplifier_ring() {
+    let program = crate::samples::FEEDBACK_LOOP.to_vec();
+    let mut network = Network::new();
+    let amps: Vec<usize> = [9, 8, 7, 6, 5]
+        .iter()
+        .map(|&phase| network.add_with_inputs(Computer::new(program.clone()), &[phase]))
+        .collect();
+    network.ring(&amps);
+    network.feed(amps[0], 0);
+    let report = network.run().unwrap();
+    assert_eq!(report.outcome, Outcome::AllHalted);
+    assert!(report.sinks.is_empty());
+    assert_eq!(report.outputs[amps[4]].last(), Some(&139629729));
+}
+
+#[test]
+pub fn fan_out_and_in() {
+    // Echo each input once, then halt.
+    let echo = |n| {
+        let mut program = Vec::new();
+        for _ in 0..n {
+            program.extend_from_slice(&[3, 100, 4, 100]);
+        }
+        program.push(99);
+        program
+    };
+    let mut network = Network::new();
+    let source = network.add_with_inputs(Computer::new(echo(2)), &[5, 6]);
+    let left = network.add(Computer::new(echo(2)));
+    let right = network.add(Computer::new(echo(2)));
+    let join = network.add(Computer::new(echo(4)));
+    network.connect(source, left);
+    network.connect(source, right);
+    network.connect(left, join);
+    network.connect(right, join);
+    let report = network.run().unwrap();
+    assert_eq!(report.outcome, Outcome::AllHalted);
+    assert_eq!(report.sink_outputs(), [(join, &[5, 6, 5, 6][..])]);
+}
+
+#[test]
+pub fn deadlock_is_reported() {
+    let mut network = Network::new();
+    let a = network.add(Computer::new(vec![3, 0, 4, 0, 99]));
+    let b = network.add(Computer::new(vec![3, 0, 4, 0, 99]));
+    let c = network.add(Computer::new(vec![104, 1, 99]));
+    network.ring(&[a, b]);
+    let report = network.run().unwrap();
+    assert_eq!(report.halt_order, [c]);
+    match report.outcome {
+        Outcome::Deadlock(diagnostic) => assert_eq!(
+            diagnostic.to_string(),
+            "machine 0: at 0, pending input []\n\
+             machine 1: at 0, pending input []\n\
+             machine 2: halted at 2\n"
+        ),
+        other => panic!("expected deadlock, got {:?}", other),
+    }
+}
+
+#[test]
+pub fn ping_pong_livelock() {
+    // Each machine echoes whatever it's sent back to the other, forever.
+    let echo = vec![3, 100, 4, 100, 1105, 1, 0];
+    let mut network = Network::new();
+    let a = network.add_with_inputs(Computer::new(echo.clone()), &[7]);
+    let b = network.add(Computer::new(echo));
+    network.ring(&[a, b]);
+    network.set_livelock_detection(Some(1000));
+    let report = network.run().unwrap();
+    match report.outcome {
+        Outcome::Livelock(diagnostic) => {
+            assert_eq!(diagnostic.machines[a].ix, 0);
+            assert_eq!(diagnostic.machines[a].pending, [7]);
+            assert!(diagnostic.machines[b].pending.is_empty());
+        }
+        other => panic!("expected livelock, got {:?}", other),
+    }
+}
+
+#[test]
+pub fn spinning_machine_is_preempted() {
+    let mut network = Network::new();
+    let spinner = network.add(Computer::new(vec![1105, 1, 0]));
+    let waiter = network.add(Computer::new(vec![3, 0, 99]));
+    network.connect(spinner, waiter);
+    network.set_livelock_detection(Some(10));
+    let report = network.run().unwrap();
+    match report.outcome {
+        Outcome::Livelock(diagnostic) => assert!(!diagnostic.machines[spinner].halted),
+        other => panic!("expected livelock, got {:?}", other),
+    }
+    assert_eq!(network.computer(spinner).step_limit(), None);
+}
+
+#[test]
+pub fn user_step_limit_still_interrupts() {
+    let mut computer = Computer::new(vec![1105, 1, 0]);
+    computer.set_step_limit(Some(5));
+    let mut network = Network::new();
+    let id = network.add(computer);
+    network.set_livelock_detection(Some(10));
+    let report = network.run().unwrap();
+    assert_eq!(
+        report.outcome,
+        Outcome::Interrupted(id, State::BudgetExhausted)
+    );
+}
+
+#[test]
+pub fn failing_machine_gets_its_limit_back() {
+    // Counts down for longer than a time slice, then runs into its counter.
+    let mut computer = Computer::new(vec![1001, 7, -1, 7, 1005, 7, 0, 30]);
+    computer.set_step_limit(Some(1000));
+    let mut network = Network::new();
+    let id = network.add(computer);
+    network.set_livelock_detection(Some(10));
+    let err = network.run().unwrap_err();
+    assert_eq!(err.machine, id);
+    assert_eq!(network.computer(id).step_limit(), Some(1000));
+}
@@ -0,0 +1,526 @@
+use crate::cfg::{self, Instruction};
+use crate::computer::{Arithmetic, Computer, Engine, ParamKind, ParamMode};
+use crate::dialect::Dialect;
+use crate::disassembler::Operand;
+use crate::fuzz::{self, End, Mismatch, Outcome};
+use std::collections::{BTreeMap, BTreeSet};
+
+// The super-instructions from `FUSED_OPCODES` that optimized programs use.
+// Rewritten copies and folded arithmetic become `MOV src, #0, dst`, with the
+// middle operand as padding. A compare followed by a JT or JF becomes `LTJ`
+// or `EQJ`, which runs the pair in one step; the jump itself stays in place
+// for anything else that reaches it.
+pub const MOV: i64 = 20;
+pub const LTJ: i64 = 21;
+pub const EQJ: i64 = 22;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Options {
+    // Trust the conventions of compiled Intcode: relative-mode accesses only
+    // touch the stack past the end of the program, computed jumps only go to
+    // return addresses, and operands the program patches to index an array
+    // only ever point at data. Day 9's self-test jumps through a table of
+    // addresses, so it doesn't qualify.
+    pub assume_compiled: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    // Position-mode reads of never-written words turned immediate.
+    pub constants: usize,
+    // Arithmetic and comparisons on constants evaluated ahead of time.
+    pub folded: usize,
+    // Copies (`ADD x, #0` and `MUL x, #1`) rewritten as `MOV`.
+    pub moves: usize,
+    // Compares fused with the jump after them.
+    pub fused: usize,
+    // Unreachable words that nothing reads or writes, zeroed or dropped.
+    pub removed: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Optimized {
+    pub program: Vec<i64>,
+    // Addresses whose initial value differs from the original, including any
+    // dropped from the end. Only code and dead words are ever changed.
+    pub changed: BTreeSet<usize>,
+    pub stats: Stats,
+}
+
+// What the reachable code can do to memory. Relative-mode accesses, and
+// position-mode ones through operands the program overwrites, can't be pinned
+// to an address, so they're only flagged.
+#[derive(Debug, Default)]
+struct Flow {
+    // Words of reachable instructions, and how many instructions cover each.
+    code: BTreeMap<usize, usize>,
+    reads: BTreeSet<usize>,
+    writes: BTreeSet<usize>,
+    // Operand words something writes to, whatever the operand's mode.
+    patched: BTreeSet<usize>,
+    patched_reads: bool,
+    patched_writes: bool,
+    relative: bool,
+    indirect: bool,
+}
+
+fn analyse(cfg: &cfg::Cfg) -> Flow {
+    let instructions = || cfg.blocks.values().flat_map(|block| &block.instructions);
+    // Taking every operand at face value, which over-approximates: a patched
+    // operand's initial value is usually just a placeholder.
+    let targets: BTreeSet<usize> = instructions()
+        .flat_map(|instruction| instruction.operands.iter().zip(instruction.info.params))
+        .filter(|(operand, kind)| {
+            operand.mode == ParamMode::Position && **kind == ParamKind::Write && operand.value >= 0
+        })
+        .map(|(operand, _)| operand.value as usize)
+        .collect();
+    let mut flow = Flow::default();
+    for instruction in instructions() {
+        let code = instruction.info.code;
+        if (code == 5 || code == 6) && instruction.operands[1].mode != ParamMode::Immediate {
+            flow.indirect = true;
+        }
+        for address in instruction.address..instruction.next() {
+            *flow.code.entry(address).or_insert(0) += 1;
+        }
+        let operands = instruction.operands.iter().zip(instruction.info.params);
+        for (word, (operand, kind)) in (instruction.address + 1..).zip(operands) {
+            if targets.contains(&word) {
+                flow.patched.insert(word);
+                match (operand.mode, kind) {
+                    (ParamMode::Position, ParamKind::Read) => flow.patched_reads = true,
+                    (ParamMode::Position, ParamKind::Write) => flow.patched_writes = true,
+                    _ => {}
+                }
+            }
+            match (operand.mode, kind) {
+                (ParamMode::Position, _) if flow.patched.contains(&word) => {}
+                (ParamMode::Immediate, _) => {}
+                (ParamMode::Relative, _) => flow.relative = true,
+                // Negative addresses fault before touching anything.
+                (ParamMode::Position, _) if operand.value < 0 => {}
+                (ParamMode::Position, ParamKind::Read) => {
+                    flow.reads.insert(operand.value as usize);
+                }
+                (ParamMode::Position, ParamKind::Write) => {
+                    flow.writes.insert(operand.value as usize);
+                }
+            }
+        }
+    }
+    flow
+}
+
+fn encode(code: i64, operands: &[Operand]) -> Vec<i64> {
+    let instruction = operands
+        .iter()
+        .enumerate()
+        .fold(code, |word, (i, operand)| {
+            word + operand.mode.digit() * 10i64.pow(i as u32 + 2)
+        });
+    let mut words = vec![instruction];
+    words.extend(operands.iter().map(|operand| operand.value));
+    words
+}
+
+// The replacement for one instruction, if there's a better one.
+fn rewrite(
+    instruction: &Instruction,
+    branch_follows: bool,
+    constant: impl Fn(i64) -> Option<i64>,
+    stats: &mut Stats,
+) -> Option<(i64, Vec<Operand>)> {
+    let info = instruction.info;
+    let mut operands = instruction.operands.clone();
+    let mut changed = false;
+    for (operand, kind) in operands.iter_mut().zip(info.params) {
+        if *kind != ParamKind::Read || operand.mode != ParamMode::Position {
+            continue;
+        }
+        if let Some(value) = constant(operand.value) {
+            *operand = Operand {
+                mode: ParamMode::Immediate,
+                value,
+            };
+            stats.constants += 1;
+            changed = true;
+        }
+    }
+    let immediate = |i: usize| {
+        operands
+            .get(i)
+            .filter(|operand| operand.mode == ParamMode::Immediate)
+            .map(|operand| operand.value)
+    };
+    let mov = |src: Operand| {
+        let pad = Operand {
+            mode: ParamMode::Immediate,
+            value: 0,
+        };
+        Some((MOV, vec![src, pad, operands[2]]))
+    };
+    // Overflow is left to happen at run time, as it would have.
+    let folded = match (info.code, immediate(0), immediate(1)) {
+        (1, Some(a), Some(b)) => a.checked_add(b),
+        (2, Some(a), Some(b)) => a.checked_mul(b),
+        (7, Some(a), Some(b)) => Some((a < b) as i64),
+        (8, Some(a), Some(b)) => Some((a == b) as i64),
+        _ => None,
+    };
+    if let Some(value) = folded {
+        stats.folded += 1;
+        return mov(Operand {
+            mode: ParamMode::Immediate,
+            value,
+        });
+    }
+    let copied = match (info.code, immediate(0), immediate(1)) {
+        (1, Some(0), _) | (2, Some(1), _) => Some(operands[1]),
+        (1, _, Some(0)) | (2, _, Some(1)) => Some(operands[0]),
+        _ => None,
+    };
+    if let Some(src) = copied {
+        stats.moves += 1;
+        return mov(src);
+    }
+    let fused = match info.code {
+        7 if branch_follows => Some(LTJ),
+        8 if branch_follows => Some(EQJ),
+        _ => None,
+    };
+    if let Some(code) = fused {
+        stats.fused += 1;
+        return Some((code, operands));
+    }
+    match changed {
+        true => Some((info.code, operands)),
+        false => None,
+    }
+}
+
+// Rewrites `program` to run the same way in fewer or cheaper steps under
+// `Dialect::fused()`. Instructions keep their length and place, so only those whose
+// words nothing reads or writes as data are touched. Programs that build or
+// redirect their own code, and (unless `assume_compiled` is set) programs using
+// relative mode, computed jumps or patched operands, are returned unchanged,
+// since what they touch can't be known here.
+pub fn optimize(program: &[i64], options: Options) -> Optimized {
+    let cfg = cfg::build(program);
+    let flow = analyse(&cfg);
+    let mut optimized = Optimized {
+        program: program.to_vec(),
+        changed: BTreeSet::new(),
+        stats: Stats::default(),
+    };
+    // Patching an operand only changes what that instruction touches, but a new
+    // opcode, a moved jump, or writing an address control can reach that doesn't
+    // decode yet means code this analysis never sees.
+    let patched = |address: &usize| flow.writes.contains(address) || flow.patched.contains(address);
+    let rebuilt = cfg
+        .invalid
+        .iter()
+        .any(|address| flow.writes.contains(address))
+        || cfg
+            .blocks
+            .values()
+            .flat_map(|block| &block.instructions)
+            .any(|instruction| {
+                let code = instruction.info.code;
+                flow.writes.contains(&instruction.address)
+                    || ((code == 5 || code == 6)
+                        && (instruction.address..instruction.next())
+                            .any(|address| patched(&address)))
+            });
+    let unpinned = flow.relative || flow.indirect || flow.patched_reads || flow.patched_writes;
+    if rebuilt || (!options.assume_compiled && unpinned) {
+        return optimized;
+    }
+
+    let constant = |address: i64| match address {
+        _ if flow.patched_writes => None,
+        0..=i64::MAX if (address as usize) < program.len() => {
+            Some(program[address as usize]).filter(|_| !flow.writes.contains(&(address as usize)))
+        }
+        _ => None,
+    };
+    let jumps: BTreeSet<usize> = cfg
+        .blocks
+        .values()
+        .flat_map(|block| &block.instructions)
+        .filter(|instruction| instruction.info.code == 5 || instruction.info.code == 6)
+        .map(|instruction| instruction.address)
+        .collect();
+    let stats = &mut optimized.stats;
+    for instruction in cfg.blocks.values().flat_map(|block| &block.instructions) {
+        let words = instruction.address..instruction.next();
+        if words.clone().any(|address| {
+            flow.reads.contains(&address) || patched(&address) || flow.code[&address] > 1
+        }) {
+            continue;
+        }
+        let branch_follows = jumps.contains(&instruction.next());
+        if let Some((code, operands)) = rewrite(instruction, branch_follows, constant, stats) {
+            optimized.program[words].copy_from_slice(&encode(code, &operands));
+        }
+    }
+
+    let dead = |address: usize| {
+        !flow.patched_reads
+            && !flow.code.contains_key(&address)
+            && !flow.reads.contains(&address)
+            && !flow.writes.contains(&address)
+            && !cfg.invalid.contains(&address)
+    };
+    for address in (0..program.len()).filter(|&address| dead(address)) {
+        optimized.program[address] = 0;
+        stats.removed += 1;
+    }
+    while !optimized.program.is_empty() && dead(optimized.program.len() - 1) {
+        optimized.program.pop();
+    }
+
+    optimized.changed = (0..program.len())
+        .filter(|&address| optimized.program.get(address) != Some(&program[address]))
+        .collect();
+    optimized
+}
+
+// Runs the original under the full instruction set and the optimized program
+// under `Dialect::fused()` on the same inputs, in checked mode, and compares
+// how they ended, what they output and their final memory, apart from the
+// words the optimizer changed. The optimized program may take fewer steps, so
+// if the original runs out, it only has to have output the same up to there.
+// Returns how the optimized program ran.
+pub fn check_equivalence(
+    original: &[i64],
+    optimized: &Optimized,
+    inputs: &[i64],
+    step_limit: u64,
+) -> Result<Outcome, Box<Mismatch>> {
+    let run = |program: &[i64], dialect: Dialect| {
+        let mut computer = Computer::new(program.to_vec());
+        computer.set_dialect(dialect);
+        computer.set_arithmetic(Arithmetic::Checked);
+        computer.set_step_limit(Some(step_limit));
+        for &input in inputs {
+            computer.add_input(input);
+        }
+        let mut outcome = fuzz::outcome(&mut computer);
+        outcome
+            .memory
+            .retain(|(address, _)| !optimized.changed.contains(address));
+        outcome
+    };
+    let expected = run(original, Dialect::day9());
+    let actual = run(&optimized.program, Dialect::fused());
+    let equivalent = actual.steps <= expected.steps
+        && match expected.end {
+            End::OutOfSteps => actual.outputs.starts_with(&expected.outputs),
+            _ => {
+                actual.end == expected.end
+                    && actual.outputs == expected.outputs
+                    && actual.memory == expected.memory
+            }
+        };
+    match equivalent {
+        true => Ok(actual),
+        false => Err(Box::new(Mismatch {
+            config: "optimized program",
+            program: original.to_vec(),
+            inputs: inputs.to_vec(),
+            expected,
+            actual,
+        })),
+    }
+}
+
+#[test]
+pub fn folds_fuses_and_removes() {
+    let program = vec![
+        1, 17, 18, 19, // ADD [17], [18], [19]: both constant
+        1001, 19, 0, 20, // ADD [19], #0, [20]: a copy
+        4, 20, // OUT [20]
+        1105, 1, 16, // JT #1, #16
+        104, 7, 99, // unreachable
+        99, // HLT
+        3, 4, // constants
+        0, 0,   // results
+        555, // never touched
+    ];
+    let optimized = optimize(&program, Options::default());
+    assert_eq!(
+        optimized.program,
+        [1120, 7, 0, 19, 1020, 19, 0, 20, 4, 20, 1105, 1, 16, 0, 0, 0, 99, 3, 4, 0, 0]
+    );
+    assert_eq!(
+        optimized.stats,
+        Stats {
+            constants: 2,
+            folded: 1,
+            moves: 1,
+            fused: 0,
+            removed: 4,
+        }
+    );
+    assert_eq!(
+        optimized.changed.iter().cloned().collect::<Vec<_>>(),
+        [0, 1, 2, 4, 13, 14, 15, 21]
+    );
+    let outcome = check_equivalence(&program, &optimized, &[], 100).unwrap();
+    assert_eq!(outcome.outputs, [7]);
+}
+
+#[test]
+pub fn compare_and_branch_fuse() {
+    let program = crate::samples::COUNTER;
+    let optimized = optimize(program, Options::default());
+    // LT [14], #3, [15] runs with the JT [15], #0 after it.
+    assert_eq!(optimized.program[6..10], [1021, 14, 3, 15]);
+    assert_eq!(optimized.program[10..13], program[10..13]);
+    assert_eq!(optimized.stats.fused, 1);
+    let outcome = check_equivalence(program, &optimized, &[], 100).unwrap();
+    assert_eq!(outcome.outputs, [1, 2, 3]);
+    // Three of the original's 13 steps are saved.
+    assert_eq!(outcome.steps, 10);
+}
+
+#[test]
+pub fn code_read_as_data_is_kept() {
+    // The first instruction reads its own operand, so must stay as it is.
+    let program = vec![1, 1, 10, 11, 2, 11, 10, 12, 99, 0, 4, 0, 0];
+    let optimized = optimize(&program, Options::default());
+    assert_eq!(optimized.program[..4], program[..4]);
+    // [10] is constant, but [11] is written by the ADD before.
+    assert_eq!(optimized.program[4..8], [1002, 11, 4, 12]);
+    check_equivalence(&program, &optimized, &[], 100).unwrap();
+}
+
+#[test]
+pub fn self_modifying_code_is_left_alone() {
+    // The input becomes the next instruction, which doesn't decode until then.
+    let program = vec![3, 2, 0, 99, 99];
+    let optimized = optimize(&program, Options::default());
+    assert_eq!(optimized.program, program);
+    let outcome = check_equivalence(&program, &optimized, &[104], 100).unwrap();
+    assert_eq!(outcome.outputs, [99]);
+}
+
+#[test]
+pub fn compiled_code_needs_the_assumption() {
+    use crate::assembler::assemble;
+
+    let program = assemble(
+        "      ARB #100
+               IN [input]
+               ADD [input], #0, rb+0
+               MUL rb+0, [two], [input]
+               OUT [input]
+               HLT
+        input: DATA 0
+        two:   DATA 2",
+    )
+    .unwrap();
+    let unchanged = optimize(&program, Options::default());
+    assert_eq!(unchanged.program, program);
+    assert!(unchanged.changed.is_empty());
+
+    let optimized = optimize(
+        &program,
+        Options {
+            assume_compiled: true,
+        },
+    );
+    assert_eq!(optimized.stats.moves, 1);
+    assert_eq!(optimized.stats.constants, 1);
+    for input in &[0, 21] {
+        let outcome = check_equivalence(&program, &optimized, &[*input], 100).unwrap();
+        assert_eq!(outcome.outputs, [input * 2]);
+    }
+}
+
+#[test]
+pub fn real_programs_are_optimized() {
+    let options = Options {
+        assume_compiled: true,
+    };
+    // The arcade patches operands to index its screen, and draws it once
+    // without any input.
+    let program = crate::day13::input_generator(include_str!("../input/2019/day13.txt"));
+    let optimized = optimize(&program, options);
+    assert!(optimized.stats.moves > 0);
+    assert!(optimized.stats.fused > 0);
+    let outcome = check_equivalence(&program, &optimized, &[], 1_000_000).unwrap();
+    assert_eq!(outcome.end, End::Halted);
+    assert!(!outcome.outputs.is_empty());
+}
+
+#[test]
+pub fn fuzzed_programs_stay_equivalent() {
+    use crate::disassembler::decode_at;
+
+    let mut rng = fuzz::Rng::new(2019);
+    let (mut changed, mut fused) = (0, 0);
+    for _ in 0..300 {
+        let (mut program, inputs) = fuzz::generate(&mut rng);
+        // Generated programs lean on relative mode, which would leave nothing
+        // to optimize; use position mode into the data area instead.
+        let data = program[1];
+        let mut address = 0;
+        while let Some((_, operands)) = decode_at(&program, address) {
+            for (i, operand) in operands.iter().enumerate() {
+                if operand.mode == ParamMode::Relative {
+                    program[address] -= 2 * 10i64.pow(i as u32 + 2);
+                    program[address + 1 + i] += data;
+                }
+            }
+            address += 1 + operands.len();
+        }
+        let optimized = optimize(&program, Options::default());
+        changed += !optimized.changed.is_empty() as usize;
+        fused += optimized.stats.fused;
+        if let Err(mismatch) = check_equivalence(&program, &optimized, &inputs, fuzz::STEP_LIMIT) {
+            panic!("{}", mismatch);
+        }
+    }
+    assert!(changed > 40, "only {} programs changed", changed);
+    assert!(fused > 0, "no compares fused");
+}
+
+// A benchmark rather than a test: `cargo test --release -- --ignored --nocapture optimized_loop_speed`.
+// The copy loop from `copy_loop_speed`, whose copy folds to a `MOV` and whose
+// compare fuses with its branch.
+#[test]
+#[ignore]
+pub fn optimized_loop_speed() {
+    // [51] = [50]; [52] += 1; [54] = [52] < [53]; if [54] goto 0; HLT
+    let mut program = vec![
+        1001, 50, 0, 51, 1001, 52, 1, 52, 7, 52, 53, 54, 1005, 54, 0, 99,
+    ];
+    program.resize(55, 0);
+    program[50] = 7;
+    program[53] = 750_000;
+    let optimized = optimize(&program, Options::default());
+    assert_eq!(optimized.stats.fused, 1);
+    let time = |program: &[i64], dialect: Dialect| {
+        (0..5)
+            .map(|_| {
+                let mut computer = Computer::new(program.to_vec());
+                computer.set_dialect(dialect.clone());
+                computer.set_engine(Engine::Cached);
+                let start = std::time::Instant::now();
+                assert_eq!(computer.run(), Ok(crate::computer::State::Complete));
+                (start.elapsed(), computer.cycles())
+            })
+            .min()
+            .unwrap()
+    };
+    let (original, original_steps) = time(&program, Dialect::day9());
+    let (faster, steps) = time(&optimized.program, Dialect::fused());
+    println!(
+        "original {:?} in {} steps, optimized {:?} in {} steps",
+        original, original_steps, faster, steps
+    );
+    assert_eq!(steps, 2_250_001);
+    assert!(faster < original);
+}
@@ -0,0 +1,246 @@
+use crate::computer::opcode_info;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+// A stretch of execution between waits for input, or up to the halt.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Slice {
+    pub cycles: u64,
+    pub elapsed: Duration,
+}
+
+// A loop found from its back edge: the jump at `end` taken back to `start`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loop {
+    pub start: usize,
+    pub end: usize,
+    pub iterations: u64,
+    // Instructions executed at addresses in `start..=end`, which includes any
+    // inner loops.
+    pub cycles: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    // Executions and the last instruction seen, per address.
+    counts: Vec<u64>,
+    instructions: Vec<i64>,
+    opcodes: BTreeMap<i64, u64>,
+    back_edges: BTreeMap<(usize, usize), u64>,
+    slices: Vec<Slice>,
+    running: Option<(Instant, u64)>,
+    cycles: u64,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn resume(&mut self) {
+        if self.running.is_none() {
+            self.running = Some((Instant::now(), self.cycles));
+        }
+    }
+
+    pub(crate) fn pause(&mut self) {
+        if let Some((started, cycles)) = self.running.take() {
+            self.slices.push(Slice {
+                cycles: self.cycles - cycles,
+                elapsed: started.elapsed(),
+            });
+        }
+    }
+
+    pub(crate) fn record(&mut self, ix: usize, instruction: i64, next: usize, fixed_target: bool) {
+        if ix >= self.counts.len() {
+            self.counts.resize(ix + 1, 0);
+            self.instructions.resize(ix + 1, 0);
+        }
+        self.counts[ix] += 1;
+        self.instructions[ix] = instruction;
+        *self.opcodes.entry(instruction % 100).or_insert(0) += 1;
+        // Only a jump back to a fixed address closes a loop. One through memory
+        // is most likely a return, and halting leaves the instruction pointer
+        // where it was without jumping at all.
+        if next <= ix && fixed_target {
+            *self.back_edges.entry((next, ix)).or_insert(0) += 1;
+        }
+        self.cycles += 1;
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn count(&self, address: usize) -> u64 {
+        self.counts.get(address).cloned().unwrap_or(0)
+    }
+
+    pub fn opcode_counts(&self) -> &BTreeMap<i64, u64> {
+        &self.opcodes
+    }
+
+    // Finished slices only; the one in progress isn't included until the
+    // machine next waits for input or halts.
+    pub fn slices(&self) -> &[Slice] {
+        &self.slices
+    }
+
+    // Executed addresses, most executed first.
+    pub fn hot_addresses(&self) -> Vec<(usize, u64)> {
+        let mut hot: Vec<(usize, u64)> = (0..self.counts.len())
+            .filter(|&address| self.counts[address] > 0)
+            .map(|address| (address, self.counts[address]))
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hot
+    }
+
+    // Loops, most expensive first.
+    pub fn hot_loops(&self) -> Vec<Loop> {
+        let mut loops: Vec<Loop> = self
+            .back_edges
+            .iter()
+            .map(|(&(start, end), &iterations)| Loop {
+                start,
+                end,
+                iterations,
+                cycles: self.counts[start..=end].iter().sum(),
+            })
+            .collect();
+        loops.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.start.cmp(&b.start)));
+        loops
+    }
+
+    pub fn report(&self, top: usize) -> String {
+        let mut out = String::new();
+        let percent = |n: u64| 100.0 * n as f64 / self.cycles.max(1) as f64;
+        let mnemonic =
+            |instruction: i64| opcode_info(instruction % 100).map_or("???", |info| info.mnemonic);
+        writeln!(out, "{} instructions", self.cycles).unwrap();
+        if !self.slices.is_empty() {
+            let total: Duration = self.slices.iter().map(|slice| slice.elapsed).sum();
+            let longest = self
+                .slices
+                .iter()
+                .max_by_key(|slice| slice.elapsed)
+                .unwrap();
+            writeln!(
+                out,
+                "{} slices between inputs, {:?} in total, longest {:?} ({} instructions)",
+                self.slices.len(),
+                total,
+                longest.elapsed,
+                longest.cycles
+            )
+            .unwrap();
+        }
+        writeln!(out, "hot loops:").unwrap();
+        for hot in self.hot_loops().iter().take(top) {
+            writeln!(
+                out,
+                "  {:>5}..={:<5} {:>10} iterations {:>12} instructions {:5.1}%",
+                hot.start,
+                hot.end,
+                hot.iterations,
+                hot.cycles,
+                percent(hot.cycles)
+            )
+            .unwrap();
+        }
+        writeln!(out, "hot addresses:").unwrap();
+        for (address, count) in self.hot_addresses().into_iter().take(top) {
+            writeln!(
+                out,
+                "  {:>5} {:<4} {:>12} {:5.1}%",
+                address,
+                mnemonic(self.instructions[address]),
+                count,
+                percent(count)
+            )
+            .unwrap();
+        }
+        writeln!(out, "opcodes:").unwrap();
+        for (&opcode, &count) in &self.opcodes {
+            writeln!(
+                out,
+                "  {:<4} {:>12} {:5.1}%",
+                mnemonic(opcode),
+                count,
+                percent(count)
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+#[test]
+pub fn counting_loop() {
+    use crate::computer::{Computer, State};
+
+    let program = crate::samples::COUNTER.to_vec();
+    let mut computer = Computer::new(program);
+    computer.set_profiling(true);
+    while computer.run() != Ok(State::Complete) {}
+    let profile = computer.profile().unwrap();
+    assert_eq!(profile.cycles(), 13);
+    assert_eq!(
+        (profile.count(0), profile.count(10), profile.count(13)),
+        (3, 3, 1)
+    );
+    assert_eq!(profile.opcode_counts()[&5], 3);
+    assert_eq!(
+        profile.hot_loops(),
+        [Loop {
+            start: 0,
+            end: 10,
+            iterations: 2,
+            cycles: 12
+        }]
+    );
+    assert_eq!(profile.hot_addresses()[0], (0, 3));
+    assert_eq!(profile.slices().len(), 1);
+    assert_eq!(profile.slices()[0].cycles, 13);
+    let report = profile.report(3);
+    assert!(report.starts_with("13 instructions\n"));
+    assert!(
+        report.contains("      0..=10             2 iterations           12 instructions  92.3%\n")
+    );
+    assert!(report.contains("      0 ADD             3  23.1%\n"));
+}
+
+#[test]
+pub fn slices_end_at_input() {
+    use crate::computer::{Computer, State};
+
+    // Echoes inputs until it reads a zero.
+    let program = vec![3, 9, 4, 9, 1005, 9, 0, 99, 0, 0];
+    let mut computer = Computer::new(program);
+    computer.set_profiling(true);
+    assert_eq!(computer.run(), Ok(State::AwaitingInput));
+    for input in &[5, 6, 0] {
+        computer.add_input(*input);
+        while let Ok(State::Output(_)) = computer.run() {}
+    }
+    let slices: Vec<u64> = computer
+        .profile()
+        .unwrap()
+        .slices()
+        .iter()
+        .map(|slice| slice.cycles)
+        .collect();
+    assert_eq!(slices, [0, 3, 3, 4]);
+}
+
+#[test]
+pub fn returns_are_not_loops() {
+    use crate::computer::{Computer, State};
+
+    let mut computer = Computer::new(crate::samples::nested_calls());
+    computer.set_profiling(true);
+    while computer.run() != Ok(State::Complete) {}
+    assert_eq!(computer.profile().unwrap().hot_loops(), []);
+}
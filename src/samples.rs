@@ -0,0 +1,43 @@
+// Small programs shared by tests across modules.
+
+// Counts [14] up to three, outputting each value.
+pub const COUNTER: &[i64] = &[
+    1001, 14, 1, 14, 4, 14, 1007, 14, 3, 15, 1005, 15, 0, 99, 0, 0,
+];
+
+// Day 7's first amplifier example: reads a phase and a signal and outputs
+// signal * 10 + phase. Phases 4, 3, 2, 1, 0 in a chain give 43210.
+pub const AMPLIFIER: &[i64] = &[
+    3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+];
+
+// Day 7's second feedback loop example. Phases 9, 8, 7, 6, 5 in a ring,
+// started with 0, leave 139629729 as the last output.
+pub const FEEDBACK_LOOP: &[i64] = &[
+    3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1, 28, 1005,
+    28, 6, 99, 0, 0, 5,
+];
+
+// Calls `twice` with 3, which calls `double` on its argument twice and
+// returns the result; outputs 12.
+pub fn nested_calls() -> Vec<i64> {
+    crate::assembler::assemble(
+        "
+            ARB #100
+            ADD #3, #0, rb+1
+            CALL twice
+            OUT rb+1
+            HLT
+    twice:  ARB #2
+            ADD rb-1, #0, rb+1
+            CALL double
+            CALL double
+            ADD rb+1, #0, rb-1
+            ARB #-2
+            RET
+    double: MUL rb+1, #2, rb+1
+            RET
+    ",
+    )
+    .unwrap()
+}
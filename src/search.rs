@@ -0,0 +1,212 @@
+use crate::computer::Computer;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+// Words to patch into a program before it runs, and inputs to queue for it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Candidate {
+    pub patches: Vec<(usize, i64)>,
+    pub inputs: Vec<i64>,
+}
+
+impl Candidate {
+    pub fn apply(&self, computer: &mut Computer) {
+        for &(address, value) in &self.patches {
+            computer.poke(address, value);
+        }
+        for &input in &self.inputs {
+            computer.add_input(input);
+        }
+    }
+}
+
+// Every combination of values for the given addresses, e.g. day 2's noun and verb.
+pub fn patch_grid(addresses: &[usize], values: &[i64]) -> Vec<Candidate> {
+    let mut grid = vec![Candidate::default()];
+    for &address in addresses {
+        grid = grid
+            .iter()
+            .flat_map(|candidate| {
+                values.iter().map(move |&value| {
+                    let mut candidate = candidate.clone();
+                    candidate.patches.push((address, value));
+                    candidate
+                })
+            })
+            .collect();
+    }
+    grid
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Best<P> {
+    pub candidate: P,
+    pub score: i64,
+    // How many candidates were scored before the search stopped.
+    pub evaluated: usize,
+}
+
+// Scores every candidate in a parameter space across a pool of threads and
+// keeps the highest. Ties go to the candidate that comes first in the space,
+// as does a target hit, so the answer doesn't depend on the thread count.
+#[derive(Debug)]
+pub struct Search<P> {
+    space: Vec<P>,
+    threads: usize,
+    target: Option<i64>,
+}
+
+impl<P: Clone + Sync> Search<P> {
+    pub fn new(space: Vec<P>) -> Self {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        Self {
+            space,
+            threads,
+            target: None,
+        }
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    // Stops early once a candidate scores exactly `target`, and returns the
+    // first such candidate in the space even if something else scored higher.
+    // Candidates before a hit are still scored, in case one of them hits too.
+    pub fn set_target(&mut self, target: Option<i64>) {
+        self.target = target;
+    }
+
+    // `objective` returns None for candidates that don't produce a score at all.
+    pub fn run<F>(&self, objective: F) -> Option<Best<P>>
+    where
+        F: Fn(&P) -> Option<i64> + Sync,
+    {
+        self.run_with(|| (), |_, candidate| objective(candidate))
+    }
+
+    // Like `run`, but each worker gets its own state from `init` to reuse between
+    // candidates, such as a machine to restore rather than rebuild.
+    pub fn run_with<S, I, F>(&self, init: I, objective: F) -> Option<Best<P>>
+    where
+        I: Fn() -> S + Sync,
+        F: Fn(&mut S, &P) -> Option<i64> + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let evaluated = AtomicUsize::new(0);
+        // Index of the first target hit so far. Indices are handed out in
+        // order, so everything before it has already been taken by a worker.
+        let first_hit = AtomicUsize::new(usize::MAX);
+        // (score, index) of the best so far.
+        let best: Mutex<Option<(i64, usize)>> = Mutex::new(None);
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(self.space.len()) {
+                scope.spawn(|| {
+                    let mut state = init();
+                    let mut local: Option<(i64, usize)> = None;
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        if index >= self.space.len() || index > first_hit.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        let score = objective(&mut state, &self.space[index]);
+                        evaluated.fetch_add(1, Ordering::Relaxed);
+                        let score = match score {
+                            Some(score) => score,
+                            None => continue,
+                        };
+                        if Some(score) == self.target {
+                            first_hit.fetch_min(index, Ordering::Relaxed);
+                        }
+                        if local.is_none_or(|(best, _)| score > best) {
+                            local = Some((score, index));
+                        }
+                    }
+                    if let Some((score, index)) = local {
+                        let mut best = best.lock().unwrap();
+                        let better = best.is_none_or(|(top, first)| {
+                            score > top || (score == top && index < first)
+                        });
+                        if better {
+                            *best = Some((score, index));
+                        }
+                    }
+                });
+            }
+        });
+
+        let found = match first_hit.into_inner() {
+            usize::MAX => best.into_inner().unwrap(),
+            index => self.target.map(|target| (target, index)),
+        };
+        found.map(|(score, index)| Best {
+            candidate: self.space[index].clone(),
+            score,
+            evaluated: evaluated.into_inner(),
+        })
+    }
+}
+
+#[test]
+pub fn best_input_is_found_on_any_thread_count() {
+    // Outputs (x - 37)^2 for input x.
+    let program = vec![3, 13, 1001, 13, -37, 13, 2, 13, 13, 13, 4, 13, 99, 0];
+    let space: Vec<Candidate> = (0..64)
+        .map(|x| Candidate {
+            patches: vec![],
+            inputs: vec![x],
+        })
+        .collect();
+    let objective = |candidate: &Candidate| {
+        let mut computer = Computer::new(program.clone());
+        candidate.apply(&mut computer);
+        match computer.run() {
+            Ok(crate::computer::State::Output(value)) => Some(-value),
+            _ => None,
+        }
+    };
+    for &threads in &[1, 4] {
+        let mut search = Search::new(space.clone());
+        search.set_threads(threads);
+        let best = search.run(objective).unwrap();
+        assert_eq!(best.candidate.inputs, [37]);
+        assert_eq!(best.score, 0);
+        assert_eq!(best.evaluated, 64);
+    }
+}
+
+#[test]
+pub fn target_stops_early() {
+    let space = patch_grid(&[1, 2], &(0..100).collect::<Vec<_>>());
+    assert_eq!(space.len(), 10_000);
+    assert_eq!(space[101].patches, [(1, 1), (2, 1)]);
+
+    // Multiplies the two patched words.
+    let program = vec![1102, 0, 0, 0, 99];
+    for &threads in &[1, 2, 8] {
+        let mut search = Search::new(space.clone());
+        search.set_threads(threads);
+        search.set_target(Some(1000));
+        let best = search
+            .run_with(
+                || {
+                    let computer = Computer::new(program.clone());
+                    let initial = computer.snapshot();
+                    (computer, initial)
+                },
+                |(computer, initial), candidate| {
+                    computer.restore(initial);
+                    candidate.apply(computer);
+                    computer.run().ok()?;
+                    Some(computer.peek(0))
+                },
+            )
+            .unwrap();
+        assert_eq!(best.score, 1000);
+        // 20 * 50 comes first of the several products that make 1000.
+        assert_eq!(best.candidate.patches, [(1, 20), (2, 50)]);
+        assert!(best.evaluated < 10_000);
+    }
+}
@@ -0,0 +1,209 @@
+use crate::memory::Memory;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 3] = b"ICS";
+const VERSION: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Snapshot {
+    // One past the highest address in use.
+    pub len: usize,
+    // The words that aren't zero, in address order.
+    pub memory: Vec<(usize, i64)>,
+    pub ix: usize,
+    pub relative_base: i64,
+    pub inputs: Vec<i64>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_i64<R: Read>(reader: &mut R) -> io::Result<i64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(i64::from_le_bytes(bytes))
+}
+
+fn read_len<R: Read>(reader: &mut R) -> io::Result<usize> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes) as usize)
+}
+
+// Lengths come from the file, so grow as we read rather than trusting them up front.
+fn read_words<R: Read>(reader: &mut R) -> io::Result<Vec<i64>> {
+    let len = read_len(reader)?;
+    let mut words = Vec::new();
+    for _ in 0..len {
+        words.push(read_i64(reader)?);
+    }
+    Ok(words)
+}
+
+fn write_words<W: Write>(writer: &mut W, words: &[i64]) -> io::Result<()> {
+    writer.write_all(&(words.len() as u64).to_le_bytes())?;
+    for word in words {
+        writer.write_all(&word.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+// Version 1 stored every word; memory far out is mostly zeros, so version 2
+// stores the length and then only the words that are set.
+fn read_sparse<R: Read>(reader: &mut R) -> io::Result<(usize, Vec<(usize, i64)>)> {
+    let len = read_len(reader)?;
+    let count = read_len(reader)?;
+    let mut words: Vec<(usize, i64)> = Vec::new();
+    for _ in 0..count {
+        let address = read_len(reader)?;
+        if address >= len || words.last().is_some_and(|&(last, _)| last >= address) {
+            return Err(invalid(format!("address {} out of order", address)));
+        }
+        words.push((address, read_i64(reader)?));
+    }
+    Ok((len, words))
+}
+
+// Layout (little-endian): "ICS", version byte, ix (u64), relative base (i64),
+// memory length (u64), the count of non-zero words (u64) and each as an
+// address (u64) and value (i64), then queued inputs as a u64 length followed
+// by i64 words.
+impl Snapshot {
+    // The value at `address`, which is zero unless it's one of the stored words.
+    pub fn get(&self, address: usize) -> i64 {
+        match self.memory.binary_search_by_key(&address, |&(a, _)| a) {
+            Ok(i) => self.memory[i].1,
+            Err(_) => 0,
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&(self.ix as u64).to_le_bytes())?;
+        writer.write_all(&self.relative_base.to_le_bytes())?;
+        writer.write_all(&(self.len as u64).to_le_bytes())?;
+        writer.write_all(&(self.memory.len() as u64).to_le_bytes())?;
+        for &(address, value) in &self.memory {
+            writer.write_all(&(address as u64).to_le_bytes())?;
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        write_words(&mut writer, &self.inputs)?;
+        writer.flush()
+    }
+
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; 4];
+        reader.read_exact(&mut header)?;
+        if &header[..3] != MAGIC {
+            return Err(invalid("not an Intcode snapshot".to_string()));
+        }
+        let ix = read_len(&mut reader)?;
+        let relative_base = read_i64(&mut reader)?;
+        let (len, memory) = match header[3] {
+            1 => {
+                let words = read_words(&mut reader)?;
+                let memory = words.nonzero().collect();
+                (words.len(), memory)
+            }
+            VERSION => read_sparse(&mut reader)?,
+            version => return Err(invalid(format!("unsupported snapshot version {}", version))),
+        };
+        let inputs = read_words(&mut reader)?;
+        Ok(Self {
+            len,
+            memory,
+            ix,
+            relative_base,
+            inputs,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+#[test]
+pub fn checkpoint_and_roll_back() {
+    use crate::computer::{Computer, State};
+
+    // Outputs 10 * input, forever.
+    let mut computer = Computer::new(vec![3, 11, 1002, 11, 10, 11, 4, 11, 1105, 1, 0, 0]);
+    computer.add_input(1);
+    assert_eq!(computer.run(), Ok(State::Output(10)));
+
+    let checkpoint = computer.snapshot();
+    computer.add_input(7);
+    assert_eq!(computer.run(), Ok(State::Output(70)));
+
+    computer.restore(&checkpoint);
+    computer.add_input(2);
+    assert_eq!(computer.run(), Ok(State::Output(20)));
+    assert_eq!(computer.run(), Ok(State::AwaitingInput));
+}
+
+#[test]
+pub fn file_round_trip() {
+    use crate::computer::{Computer, State};
+
+    let mut computer = Computer::new(vec![109, -7, 3, 20, 3, 21, 99]);
+    computer.add_input(-5);
+    computer.add_input(6);
+    computer.add_input(i64::MIN);
+    assert_eq!(computer.run(), Ok(State::Complete));
+    let snapshot = computer.snapshot();
+    assert_eq!(snapshot.inputs, [i64::MIN]);
+
+    let path = std::env::temp_dir().join(format!("intcode-{}.snapshot", std::process::id()));
+    snapshot.save(&path).unwrap();
+    let loaded = Snapshot::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.unwrap(), snapshot);
+
+    let restored = Computer::from_snapshot(snapshot.clone());
+    assert_eq!(restored.snapshot(), snapshot);
+}
+
+#[test]
+pub fn reads_version_one() {
+    let mut bytes = b"ICS\x01".to_vec();
+    for word in &[3, -2, 3, 0, 99, 0] {
+        bytes.extend_from_slice(&(*word as i64).to_le_bytes());
+    }
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    let snapshot = Snapshot::read_from(bytes.as_slice()).unwrap();
+    assert_eq!(
+        (snapshot.ix, snapshot.relative_base, snapshot.len),
+        (3, -2, 3)
+    );
+    assert_eq!(snapshot.memory, [(1, 99)]);
+}
+
+#[test]
+pub fn rejects_bad_headers() {
+    let snapshot = Snapshot {
+        len: 1,
+        memory: vec![(0, 99)],
+        ix: 0,
+        relative_base: 0,
+        inputs: vec![],
+    };
+    let mut bytes = Vec::new();
+    snapshot.write_to(&mut bytes).unwrap();
+
+    let mut wrong_version = bytes.clone();
+    wrong_version[3] = 3;
+    let err = Snapshot::read_from(wrong_version.as_slice()).unwrap_err();
+    assert_eq!(err.to_string(), "unsupported snapshot version 3");
+
+    assert!(Snapshot::read_from(&b"ICT\x01"[..]).is_err());
+    assert!(Snapshot::read_from(&bytes[..bytes.len() - 1]).is_err());
+}
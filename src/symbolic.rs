@@ -0,0 +1,414 @@
+use crate::computer::{opcode_info, ParamKind, ParamMode};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+
+// A polynomial with integer coefficients over the variables x0, x1, ..., keyed
+// by each monomial's exponents. Zero coefficients are never stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    terms: BTreeMap<Vec<u32>, i64>,
+}
+
+impl Expr {
+    pub fn constant(value: i64, variables: usize) -> Self {
+        let mut terms = BTreeMap::new();
+        if value != 0 {
+            terms.insert(vec![0; variables], value);
+        }
+        Self { terms }
+    }
+
+    pub fn variable(index: usize, variables: usize) -> Self {
+        let mut exponents = vec![0; variables];
+        exponents[index] = 1;
+        let mut terms = BTreeMap::new();
+        terms.insert(exponents, 1);
+        Self { terms }
+    }
+
+    pub fn as_constant(&self) -> Option<i64> {
+        match self.terms.iter().next() {
+            None => Some(0),
+            Some((exponents, &c)) if self.terms.len() == 1 && exponents.iter().all(|&e| e == 0) => {
+                Some(c)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_linear(&self) -> bool {
+        self.terms.keys().all(|e| e.iter().sum::<u32>() <= 1)
+    }
+
+    // None on overflow.
+    pub fn add(&self, other: &Expr) -> Option<Expr> {
+        let mut terms = self.terms.clone();
+        for (exponents, &c) in &other.terms {
+            let sum = terms.get(exponents).unwrap_or(&0).checked_add(c)?;
+            if sum == 0 {
+                terms.remove(exponents);
+            } else {
+                terms.insert(exponents.clone(), sum);
+            }
+        }
+        Some(Expr { terms })
+    }
+
+    pub fn mul(&self, other: &Expr) -> Option<Expr> {
+        let mut product = Expr {
+            terms: BTreeMap::new(),
+        };
+        for (a, &x) in &self.terms {
+            for (b, &y) in &other.terms {
+                let exponents = a
+                    .iter()
+                    .zip(b)
+                    .map(|(i, j)| i.checked_add(*j))
+                    .collect::<Option<_>>()?;
+                let mut term = BTreeMap::new();
+                term.insert(exponents, x.checked_mul(y)?);
+                product = product.add(&Expr { terms: term })?;
+            }
+        }
+        Some(product)
+    }
+
+    pub fn eval(&self, values: &[i64]) -> Option<i64> {
+        self.terms.iter().try_fold(0i64, |sum, (exponents, &c)| {
+            let term = exponents
+                .iter()
+                .zip(values)
+                .try_fold(c, |term, (&e, &v)| term.checked_mul(v.checked_pow(e)?))?;
+            sum.checked_add(term)
+        })
+    }
+
+    // Higher powers of earlier variables first, e.g. "460800 * noun + verb + 2890696".
+    pub fn describe(&self, names: &[&str]) -> String {
+        if self.terms.is_empty() {
+            return "0".to_string();
+        }
+        let mut text = String::new();
+        for (exponents, &c) in self.terms.iter().rev() {
+            let factors: Vec<String> = exponents
+                .iter()
+                .enumerate()
+                .filter(|(_, &e)| e > 0)
+                .map(|(i, &e)| match e {
+                    1 => names[i].to_string(),
+                    _ => format!("{}^{}", names[i], e),
+                })
+                .collect();
+            let sign = if c < 0 { "-" } else { "+" };
+            if text.is_empty() {
+                if c < 0 {
+                    text.push('-');
+                }
+            } else {
+                text.push_str(&format!(" {} ", sign));
+            }
+            let magnitude = c.unsigned_abs();
+            if factors.is_empty() {
+                text.push_str(&magnitude.to_string());
+            } else if magnitude == 1 {
+                text.push_str(&factors.join(" * "));
+            } else {
+                text.push_str(&format!("{} * {}", magnitude, factors.join(" * ")));
+            }
+        }
+        text
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<String> = (0..self.terms.keys().next().map_or(0, |e| e.len()))
+            .map(|i| format!("x{}", i))
+            .collect();
+        let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+        write!(f, "{}", self.describe(&names))
+    }
+}
+
+// What a memory cell holds during symbolic execution. `Unknown` is what comes
+// of reading through a symbolic address or comparing symbolic values: fine to
+// carry around and overwrite, but fatal if it ends up steering the program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Known(Expr),
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unsupported {
+    pub ix: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.reason, self.ix)
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+// Anything longer is treated as a loop we can't see the end of.
+const STEP_LIMIT: usize = 100_000;
+
+// Writes past this are treated as a runaway address rather than memory to
+// allocate.
+const MEMORY_LIMIT: usize = 1 << 20;
+
+// Runs `program` with the words at `variables` replaced by unknowns and returns
+// final memory. Only code whose control flow doesn't depend on the variables
+// can be followed, and it mustn't do any I/O.
+pub fn execute(program: &[i64], variables: &[usize]) -> Result<Vec<Value>, Unsupported> {
+    let n = variables.len();
+    let mut memory: Vec<Value> = program
+        .iter()
+        .map(|&word| Value::Known(Expr::constant(word, n)))
+        .collect();
+    for (i, &address) in variables.iter().enumerate() {
+        if address >= memory.len() {
+            memory.resize(address + 1, Value::Known(Expr::constant(0, n)));
+        }
+        memory[address] = Value::Known(Expr::variable(i, n));
+    }
+    let zero = Value::Known(Expr::constant(0, n));
+    let mut ix = 0;
+    let mut relative_base = 0;
+
+    for _ in 0..STEP_LIMIT {
+        let at = ix;
+        let fail = |reason| Err(Unsupported { ix: at, reason });
+        let word = |address: usize| memory.get(address).unwrap_or(&zero).clone();
+        let concrete = |value: &Value| match value {
+            Value::Known(expr) => expr.as_constant(),
+            Value::Unknown => None,
+        };
+
+        let instruction = match concrete(&word(ix)) {
+            Some(instruction) => instruction,
+            None => return fail("symbolic instruction"),
+        };
+        let info = match opcode_info(instruction % 100) {
+            Some(info) => info,
+            None => return fail("unknown opcode"),
+        };
+        let mut modes = instruction / 100;
+        let mut reads = Vec::new();
+        let mut target = None;
+        for (i, kind) in info.params.iter().enumerate() {
+            let raw = word(ix + 1 + i);
+            let mode = match ParamMode::from_digit(modes % 10) {
+                Some(mode) => mode,
+                None => return fail("invalid parameter mode"),
+            };
+            modes /= 10;
+            let address = match mode {
+                ParamMode::Immediate => None,
+                ParamMode::Position => Some(concrete(&raw)),
+                ParamMode::Relative => match concrete(&raw).map(|a| a.checked_add(relative_base)) {
+                    Some(None) => return fail("address overflow"),
+                    address => Some(address.flatten()),
+                },
+            };
+            match (kind, address) {
+                (ParamKind::Read, None) => reads.push(raw),
+                (ParamKind::Read, Some(Some(a))) if a >= 0 => reads.push(word(a as usize)),
+                (ParamKind::Read, Some(Some(_))) => return fail("negative address"),
+                (ParamKind::Read, Some(None)) => reads.push(Value::Unknown),
+                (ParamKind::Write, Some(Some(a))) if a >= 0 => target = Some(a as usize),
+                (ParamKind::Write, Some(None)) => return fail("write to a symbolic address"),
+                (ParamKind::Write, _) => return fail("invalid write parameter"),
+            }
+        }
+        let next = ix + 1 + info.params.len();
+
+        let result = match info.code {
+            1 | 2 => match (&reads[0], &reads[1]) {
+                (Value::Known(a), Value::Known(b)) => {
+                    let value = if info.code == 1 { a.add(b) } else { a.mul(b) };
+                    match value {
+                        Some(value) => Value::Known(value),
+                        None => return fail("overflow"),
+                    }
+                }
+                _ => Value::Unknown,
+            },
+            7 | 8 => match (concrete(&reads[0]), concrete(&reads[1])) {
+                (Some(a), Some(b)) => {
+                    let holds = if info.code == 7 { a < b } else { a == b };
+                    Value::Known(Expr::constant(holds as i64, n))
+                }
+                _ => Value::Unknown,
+            },
+            5 | 6 => {
+                let condition = match concrete(&reads[0]) {
+                    Some(condition) => condition,
+                    None => return fail("data-dependent jump"),
+                };
+                ix = next;
+                if (condition != 0) == (info.code == 5) {
+                    match concrete(&reads[1]) {
+                        Some(t) if t >= 0 => ix = t as usize,
+                        _ => return fail("data-dependent jump"),
+                    }
+                }
+                continue;
+            }
+            9 => {
+                match concrete(&reads[0]) {
+                    Some(offset) => match relative_base.checked_add(offset) {
+                        Some(base) => relative_base = base,
+                        None => return fail("relative base overflow"),
+                    },
+                    None => return fail("symbolic relative base"),
+                }
+                ix = next;
+                continue;
+            }
+            99 => return Ok(memory),
+            _ => return fail("input or output"),
+        };
+        let target = target.unwrap();
+        if target >= MEMORY_LIMIT {
+            return fail("write too far out");
+        }
+        if target >= memory.len() {
+            memory.resize(target + 1, zero.clone());
+        }
+        memory[target] = result;
+        ix = next;
+    }
+    Err(Unsupported {
+        ix,
+        reason: "no halt within the step limit",
+    })
+}
+
+// Values for the variables, each drawn from `range`, that make a linear `expr`
+// equal `target`. The last variable is solved for directly rather than searched.
+pub fn solve(expr: &Expr, target: i64, range: Range<i64>) -> Option<Vec<i64>> {
+    let n = expr.terms.keys().next().map_or(0, |e| e.len());
+    if n == 0 || !expr.is_linear() {
+        return None;
+    }
+    let mut last = vec![0; n];
+    last[n - 1] = 1;
+    let coefficient = *expr.terms.get(&last).unwrap_or(&0);
+    let mut rest = expr.clone();
+    rest.terms.remove(&last);
+
+    let mut values = vec![range.start; n];
+    loop {
+        values[n - 1] = 0;
+        let remainder = target.checked_sub(rest.eval(&values)?)?;
+        let solution = match coefficient {
+            0 if remainder == 0 => Some(range.start),
+            0 => None,
+            c if remainder.checked_rem(c)? == 0 => remainder.checked_div(c),
+            _ => None,
+        };
+        if let Some(v) = solution.filter(|v| range.contains(v)) {
+            values[n - 1] = v;
+            return Some(values);
+        }
+        // Odometer over everything but the last variable.
+        let mut i = 0;
+        loop {
+            if i + 1 >= n {
+                return None;
+            }
+            values[i] += 1;
+            if values[i] < range.end {
+                break;
+            }
+            values[i] = range.start;
+            i += 1;
+        }
+    }
+}
+
+#[test]
+pub fn straight_line_program() {
+    // [0] = ([noun] + [verb]) is clobbered, then [0] = (noun + 3) * verb - 2 * noun.
+    let program = vec![
+        1, 0, 0, 0, 1001, 1, 3, 21, 2, 21, 2, 21, 1002, 1, -2, 22, 1, 21, 22, 0, 99,
+    ];
+    let memory = execute(&program, &[1, 2]).unwrap();
+    let expr = match &memory[0] {
+        Value::Known(expr) => expr.clone(),
+        Value::Unknown => panic!("result should be known"),
+    };
+    assert_eq!(
+        expr.describe(&["noun", "verb"]),
+        "noun * verb - 2 * noun + 3 * verb"
+    );
+    assert_eq!(expr.to_string(), "x0 * x1 - 2 * x0 + 3 * x1");
+    assert_eq!(expr.eval(&[4, 5]), Some(27));
+    assert!(!expr.is_linear());
+    assert_eq!(solve(&expr, 27, 0..100), None);
+}
+
+#[test]
+pub fn linear_inversion() {
+    // [0] = 1000 * noun + verb + 7
+    let program = vec![1, 0, 0, 3, 1002, 1, 1000, 0, 1, 0, 2, 0, 1001, 0, 7, 0, 99];
+    let memory = execute(&program, &[1, 2]).unwrap();
+    let expr = match &memory[0] {
+        Value::Known(expr) => expr.clone(),
+        Value::Unknown => panic!("result should be known"),
+    };
+    assert_eq!(expr.describe(&["noun", "verb"]), "1000 * noun + verb + 7");
+    assert_eq!(solve(&expr, 42_057, 0..100), Some(vec![42, 50]));
+    assert_eq!(solve(&expr, 200_000, 0..100), None);
+}
+
+#[test]
+pub fn data_dependent_jump() {
+    let program = vec![1005, 1, 6, 1101, 0, 0, 99];
+    assert_eq!(
+        execute(&program, &[1]),
+        Err(Unsupported {
+            ix: 0,
+            reason: "data-dependent jump"
+        })
+    );
+    let program = vec![3, 0, 99];
+    assert_eq!(
+        execute(&program, &[]).unwrap_err().reason,
+        "input or output"
+    );
+}
+
+#[test]
+pub fn runaway_addresses() {
+    let unsupported = |program: &[i64]| execute(program, &[]).unwrap_err().reason;
+    // ARB #MAX; ARB #1
+    assert_eq!(
+        unsupported(&[109, i64::MAX, 109, 1, 99]),
+        "relative base overflow"
+    );
+    // ARB #1; ADD #1, #1, rb+MAX
+    assert_eq!(
+        unsupported(&[109, 1, 21101, 1, 1, i64::MAX, 99]),
+        "address overflow"
+    );
+    // ADD #1, #1, [1 << 40]
+    assert_eq!(unsupported(&[1101, 1, 1, 1 << 40, 99]), "write too far out");
+    // Squaring x0 over and over runs its exponent out of range.
+    let program = vec![2, 8, 8, 8, 1105, 1, 0, 99, 0];
+    assert_eq!(execute(&program, &[8]).unwrap_err().reason, "overflow");
+    // -x0 = MIN has no solution in i64.
+    assert_eq!(
+        solve(
+            &Expr::variable(0, 1).mul(&Expr::constant(-1, 1)).unwrap(),
+            i64::MIN,
+            0..1
+        ),
+        None
+    );
+}
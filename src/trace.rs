@@ -0,0 +1,242 @@
+use crate::computer::{TraceEvent, Tracer};
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 4] = b"ICT\x01";
+
+pub fn to_json(event: &TraceEvent) -> String {
+    let operands: Vec<String> = event.operands.iter().map(|n| n.to_string()).collect();
+    let writes: Vec<String> = event
+        .writes
+        .iter()
+        .map(|(address, value)| format!("[{},{}]", address, value))
+        .collect();
+    format!(
+        "{{\"ix\":{},\"instruction\":{},\"op\":\"{}\",\"operands\":[{}],\"writes\":[{}],\"rb\":{}}}",
+        event.ix,
+        event.instruction,
+        event.mnemonic(),
+        operands.join(","),
+        writes.join(","),
+        event.relative_base
+    )
+}
+
+// Streams one JSON object per executed instruction. Write errors are held back
+// until `finish`, since the VM has no way to report them mid-run.
+#[derive(Debug)]
+pub struct JsonLines<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> JsonLines<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            error: None,
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.error {
+            Some(err) => Err(err),
+            None => self.writer.flush().map(|_| self.writer),
+        }
+    }
+}
+
+impl<W: Write> Tracer for JsonLines<W> {
+    fn record(&mut self, event: &TraceEvent) {
+        if self.error.is_none() {
+            self.error = writeln!(self.writer, "{}", to_json(event)).err();
+        }
+    }
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut n: u64) -> io::Result<()> {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn write_signed<W: Write>(writer: &mut W, n: i64) -> io::Result<()> {
+    write_varint(writer, ((n << 1) ^ (n >> 63)) as u64)
+}
+
+// Returns None on a clean end of stream before the first byte.
+fn read_varint<R: Read>(reader: &mut R) -> io::Result<Option<u64>> {
+    let mut n: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0u8];
+        if reader.read(&mut byte)? == 0 {
+            if shift == 0 {
+                return Ok(None);
+            }
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if shift >= 64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "varint too long",
+            ));
+        }
+        n |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(n));
+        }
+        shift += 7;
+    }
+}
+
+fn read_unsigned<R: Read>(reader: &mut R) -> io::Result<u64> {
+    read_varint(reader)?.ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+}
+
+fn read_signed<R: Read>(reader: &mut R) -> io::Result<i64> {
+    let n = read_unsigned(reader)?;
+    Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+}
+
+fn write_event<W: Write>(writer: &mut W, event: &TraceEvent) -> io::Result<()> {
+    write_varint(writer, event.ix as u64)?;
+    write_signed(writer, event.instruction)?;
+    write_varint(writer, event.operands.len() as u64)?;
+    for operand in &event.operands {
+        write_signed(writer, *operand)?;
+    }
+    write_varint(writer, event.writes.len() as u64)?;
+    for (address, value) in &event.writes {
+        write_varint(writer, *address as u64)?;
+        write_signed(writer, *value)?;
+    }
+    write_signed(writer, event.relative_base)
+}
+
+// Varint-encoded events behind a 4 byte magic/version header.
+#[derive(Debug)]
+pub struct Binary<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Binary<W> {
+    pub fn new(mut writer: W) -> Self {
+        let error = writer.write_all(MAGIC).err();
+        Self { writer, error }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.error {
+            Some(err) => Err(err),
+            None => self.writer.flush().map(|_| self.writer),
+        }
+    }
+}
+
+impl<W: Write> Tracer for Binary<W> {
+    fn record(&mut self, event: &TraceEvent) {
+        if self.error.is_none() {
+            self.error = write_event(&mut self.writer, event).err();
+        }
+    }
+}
+
+pub fn read_binary<R: Read>(mut reader: R) -> io::Result<Vec<TraceEvent>> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an Intcode trace",
+        ));
+    }
+    let mut events = Vec::new();
+    while let Some(ix) = read_varint(&mut reader)? {
+        let instruction = read_signed(&mut reader)?;
+        let operands = (0..read_unsigned(&mut reader)?)
+            .map(|_| read_signed(&mut reader))
+            .collect::<io::Result<Vec<_>>>()?;
+        let writes = (0..read_unsigned(&mut reader)?)
+            .map(|_| {
+                Ok((
+                    read_unsigned(&mut reader)? as usize,
+                    read_signed(&mut reader)?,
+                ))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let relative_base = read_signed(&mut reader)?;
+        events.push(TraceEvent {
+            ix: ix as usize,
+            instruction,
+            operands,
+            writes,
+            relative_base,
+        });
+    }
+    Ok(events)
+}
+
+// Index of the first event where two traces disagree, if they differ at all.
+pub fn first_divergence(a: &[TraceEvent], b: &[TraceEvent]) -> Option<usize> {
+    match a.iter().zip(b).position(|(x, y)| x != y) {
+        Some(i) => Some(i),
+        None if a.len() != b.len() => Some(a.len().min(b.len())),
+        None => None,
+    }
+}
+
+fn amp_trace(phase: i64) -> Vec<TraceEvent> {
+    use crate::computer::{Computer, State};
+    use std::sync::{Arc, Mutex};
+
+    let program = crate::samples::AMPLIFIER.to_vec();
+    let trace = Arc::new(Mutex::new(Vec::new()));
+    let mut computer = Computer::new(program);
+    computer.set_tracer(trace.clone());
+    computer.add_input(phase);
+    computer.add_input(0);
+    while computer.run().unwrap() != State::Complete {}
+    drop(computer);
+    Arc::try_unwrap(trace).unwrap().into_inner().unwrap()
+}
+
+#[test]
+pub fn json_lines() {
+    let mut tracer = JsonLines::new(Vec::new());
+    for event in amp_trace(4).iter().take(2) {
+        tracer.record(event);
+    }
+    let text = String::from_utf8(tracer.finish().unwrap()).unwrap();
+    assert_eq!(
+        text,
+        "{\"ix\":0,\"instruction\":3,\"op\":\"IN\",\"operands\":[15],\"writes\":[[15,4]],\"rb\":0}\n\
+         {\"ix\":2,\"instruction\":3,\"op\":\"IN\",\"operands\":[16],\"writes\":[[16,0]],\"rb\":0}\n"
+    );
+}
+
+#[test]
+pub fn binary_round_trip() {
+    let events = amp_trace(3);
+    let mut tracer = Binary::new(Vec::new());
+    for event in &events {
+        tracer.record(event);
+    }
+    let bytes = tracer.finish().unwrap();
+    assert_eq!(read_binary(bytes.as_slice()).unwrap(), events);
+    assert!(read_binary(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+pub fn diff_amplifier_runs() {
+    let (a, b) = (amp_trace(0), amp_trace(1));
+    assert_eq!(a.len(), 6);
+    assert_eq!(first_divergence(&a, &a), None);
+    assert_eq!(first_divergence(&a, &b), Some(0));
+    assert_eq!(first_divergence(&a[1..3], &b[1..]), Some(2));
+}